@@ -0,0 +1,295 @@
+use std::fmt::Display;
+use std::sync::Mutex as SyncMutex;
+
+use crossbeam_channel::Receiver;
+use futures::future::BoxFuture;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::handler::{self, ClientConfig, HandleError, Handler, Outputs, Response};
+use crate::heartbeat;
+use crate::message as msg;
+
+type OutputSink = Box<dyn Sink<String, Error = String> + Send + Unpin>;
+
+#[derive(Error,Debug)]
+pub enum AsyncClientError {
+    #[error("{input:?} stream ended")]
+    StreamEnded{ input: Outputs },
+}
+
+/// Async counterpart of `handler::Handler`.
+pub trait AsyncHandler {
+    fn handle<'a>(&'a self, json: String, msg_type: msg::Message) -> BoxFuture<'a, Result<Response,HandleError>>;
+    /// Send a message the client made itself, like the answer to a ping.
+    fn inject<'a>(&'a self, json: String, output: Outputs) -> BoxFuture<'a, Result<Response,HandleError>>;
+    fn add_output_sink<S>(&mut self, output_type: Outputs, sink: S)
+        where S: Sink<String> + Send + Unpin + 'static, S::Error: Display;
+}
+
+/// Runs a synchronous `Handler` and sends what it routes into sinks, so
+/// the async client routes exactly like `client::Client`.
+pub struct AsyncMessageHandler {
+    inner: SyncMutex<Box<dyn Handler + Send>>,
+    // What the inner handler sends, in the order the outputs were added.
+    outputs: Vec<(Outputs, Receiver<String>, Mutex<OutputSink>)>,
+}
+
+impl AsyncHandler for AsyncMessageHandler {
+    fn handle<'a>(&'a self, json: String, msg_type: msg::Message) -> BoxFuture<'a, Result<Response,HandleError>> {
+        Box::pin(async move {
+            let response = self.inner.lock().unwrap().handle(json, msg_type);
+            let flushed = self.flush().await;
+            response.and_then(|response| flushed.map(|_| response))
+        })
+    }
+
+    fn inject<'a>(&'a self, json: String, output: Outputs) -> BoxFuture<'a, Result<Response,HandleError>> {
+        Box::pin(async move {
+            let response = self.inner.lock().unwrap().inject(json, output);
+            let flushed = self.flush().await;
+            response.and_then(|response| flushed.map(|_| response))
+        })
+    }
+
+    fn add_output_sink<S>(&mut self, output_type: Outputs, sink: S)
+        where S: Sink<String> + Send + Unpin + 'static, S::Error: Display {
+        let (sender, routed) = crossbeam_channel::unbounded();
+        self.inner.get_mut().unwrap().add_output_channel(output_type.clone(), sender);
+        let sink: OutputSink = Box::new(sink.sink_map_err(|e| e.to_string()));
+        self.outputs.retain(|(output, _, _)| *output != output_type);
+        self.outputs.push((output_type, routed, Mutex::new(sink)));
+    }
+}
+
+impl AsyncMessageHandler {
+    pub fn new(client_config: ClientConfig) -> Self {
+        AsyncMessageHandler::with_handler(Box::new(handler::MessageHandler::new(client_config)))
+    }
+
+    /// Route with `handler`, like a `MessageHandler` with a validator or a
+    /// `Middleware` stack. Messages a layer holds back go out with a later
+    /// message, the async client doesn't tick.
+    pub fn with_handler(handler: Box<dyn Handler + Send>) -> Self {
+        AsyncMessageHandler{
+            inner: SyncMutex::new(handler),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Send everything the inner handler routed. Keeps going after an error
+    /// and returns the first one.
+    async fn flush(&self) -> Result<(), HandleError> {
+        let mut result = Ok(());
+        for (output, routed, sink) in &self.outputs {
+            for json in routed.try_iter() {
+                let sent = sink.lock().await.send(json).await
+                    .map_err(|e| HandleError::SinkError{
+                        reason: e,
+                        output: output.clone(),
+                    });
+                result = result.and(sent);
+            }
+        }
+        result
+    }
+}
+
+/// Async counterpart of `client::Client`, reads from streams instead of
+/// crossbeam channels.
+pub struct AsyncClient<H, S, B> {
+    handler: H,
+    inc_server_stream: S,
+    inc_bot_stream: B,
+}
+
+impl<H, S, B> AsyncClient<H, S, B>
+    where H: AsyncHandler,
+          S: Stream<Item = String> + Unpin,
+          B: Stream<Item = String> + Unpin {
+    pub fn new(handler: H, inc_server_stream: S, inc_bot_stream: B) -> Self {
+        AsyncClient{
+            handler,
+            inc_server_stream,
+            inc_bot_stream,
+        }
+    }
+
+    /// Handle incoming messages until one of the streams ends.
+    pub async fn start(mut self) -> Result<(), AsyncClientError> {
+        loop {
+            let (message, source) = tokio::select!{
+                m = self.inc_server_stream.next() => (m, Outputs::Server),
+                m = self.inc_bot_stream.next() => (m, Outputs::Bot),
+            };
+            match message {
+                Some(message) => self.handle(message, source).await,
+                None => return Err(AsyncClientError::StreamEnded{ input: source }),
+            }
+        }
+    }
+
+    async fn handle(&self, message_string: String, source: Outputs) {
+        let message = match msg::deserialize_message(&message_string) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::warn!(from = ?source, error = %e, "dropping message");
+                return;
+            },
+        };
+        let result = match message {
+            msg::Message::Ping(ping) => heartbeat::answer(ping, source.clone(), |pong, output| self.handler.inject(pong, output)).await,
+            message => self.handler.handle(message_string, message).await,
+        };
+        if let Err(e) = result {
+            tracing::warn!(from = ?source, error = %e, "handle message");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::suite;
+    use futures::channel::mpsc;
+
+    fn default_client_config() -> ClientConfig {
+        ClientConfig::builder()
+            .game("test_game")
+            .name("test_bot")
+            .build()
+            .unwrap()
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    struct AsyncHarness;
+
+    impl suite::Harness for AsyncHarness {
+        fn exchange(msg_to_send: &str, output_type: Outputs) -> String {
+            let (svr_inc_snd, svr_inc_rec) = mpsc::unbounded();
+            let (bot_inc_snd, bot_inc_rec) = mpsc::unbounded();
+            let (output_snd, mut output_rec) = mpsc::unbounded();
+
+            let mut handler = AsyncMessageHandler::new(default_client_config());
+            handler.add_output_sink(output_type.clone(), output_snd);
+            let client = AsyncClient::new(handler, svr_inc_rec, bot_inc_rec);
+
+            let input_msg = msg_to_send.to_string();
+            match output_type {
+                Outputs::Server => svr_inc_snd.unbounded_send(input_msg).unwrap(),
+                Outputs::Bot => bot_inc_snd.unbounded_send(input_msg).unwrap(),
+                Outputs::Viewer => panic!("a viewer has no input"),
+            };
+
+            block_on(async move {
+                tokio::select!{
+                    result = client.start() => panic!("Expected client to keep running but got {:?}", result),
+                    m = output_rec.next() => m.unwrap(),
+                }
+            })
+        }
+    }
+
+    suite::routing_tests!(AsyncHarness);
+
+    #[test]
+    fn client_stops_when_input_stream_ends() {
+        let handler = AsyncMessageHandler::new(default_client_config());
+        let client = AsyncClient::new(handler, futures::stream::empty(), futures::stream::pending());
+
+        assert!(block_on(client.start()).is_err());
+    }
+
+    #[test]
+    fn bot_ping_is_answered_to_the_bot() {
+        let (bot_inc_snd, bot_inc_rec) = mpsc::unbounded();
+        let (server_snd, mut server_rec) = mpsc::unbounded::<String>();
+        let (bot_snd, mut bot_rec) = mpsc::unbounded();
+        let mut handler = AsyncMessageHandler::new(default_client_config());
+        handler.add_output_sink(Outputs::Server, server_snd);
+        handler.add_output_sink(Outputs::Bot, bot_snd);
+        let client = AsyncClient::new(handler, futures::stream::pending(), bot_inc_rec);
+
+        bot_inc_snd.unbounded_send(r#"{"type":"Ping"}"#.to_string()).unwrap();
+        let pong = block_on(async move {
+            tokio::select!{
+                result = client.start() => panic!("Expected client to keep running but got {:?}", result),
+                m = bot_rec.next() => m.unwrap(),
+            }
+        });
+        assert_eq!(pong, r#"{"type":"Pong"}"#);
+        assert!(server_rec.try_recv().is_err());
+    }
+
+    #[test]
+    fn invalid_message_is_dropped() {
+        let (svr_inc_snd, svr_inc_rec) = mpsc::unbounded();
+        let (bot_snd, mut bot_rec) = mpsc::unbounded();
+        let mut handler = AsyncMessageHandler::new(default_client_config());
+        handler.add_output_sink(Outputs::Bot, bot_snd);
+        let client = AsyncClient::new(handler, svr_inc_rec, futures::stream::pending());
+
+        svr_inc_snd.unbounded_send("not json".to_string()).unwrap();
+        svr_inc_snd.unbounded_send(r#"{"type":"State"}"#.to_string()).unwrap();
+        let state = block_on(async move {
+            tokio::select!{
+                result = client.start() => panic!("Expected client to keep running but got {:?}", result),
+                m = bot_rec.next() => m.unwrap(),
+            }
+        });
+        assert_eq!(state, r#"{"type":"State"}"#);
+    }
+
+    #[test]
+    fn middleware_applies_to_async_routing() {
+        use crate::middleware::Middleware;
+        use crate::rate_limit::{Limit, LimitPolicy, RateLimit};
+
+        let rate_limit = RateLimit::new(LimitPolicy::Error).limit("Action", Limit{ per_second: 1.0, burst: 1 }).unwrap();
+        let middleware = Middleware::new(Box::new(handler::MessageHandler::new(default_client_config()))).layer(rate_limit);
+        let mut handler = AsyncMessageHandler::with_handler(Box::new(middleware));
+        let (server_snd, mut server_rec) = mpsc::unbounded();
+        let (bot_snd, mut bot_rec) = mpsc::unbounded();
+        handler.add_output_sink(Outputs::Server, server_snd);
+        handler.add_output_sink(Outputs::Bot, bot_snd);
+
+        let action = r#"{"type":"Action"}"#;
+        for _ in 0..2 {
+            block_on(handler.handle(action.to_string(), msg::deserialize_message(action).unwrap())).unwrap();
+        }
+        assert_eq!(server_rec.try_recv().unwrap(), action);
+        assert!(server_rec.try_recv().is_err());
+        assert!(bot_rec.try_recv().unwrap().contains("rate limit exceeded"));
+    }
+
+    #[test]
+    fn ended_stream_is_reported() {
+        let handler = AsyncMessageHandler::new(default_client_config());
+        let client = AsyncClient::new(handler, futures::stream::pending(), futures::stream::empty());
+
+        assert!(matches!(block_on(client.start()), Err(AsyncClientError::StreamEnded{ input: Outputs::Bot })));
+    }
+
+    #[test]
+    fn trigger_sink_error_by_closing_output() {
+        let mut handler = AsyncMessageHandler::new(default_client_config());
+        let (output_snd, output_rec) = mpsc::unbounded::<String>();
+        drop(output_rec);
+        handler.add_output_sink(Outputs::Server, output_snd);
+
+        let msg_json = r#"{"type": "Connected"}"#.to_string();
+        let message = msg::deserialize_message(&msg_json).unwrap();
+        let returned_err = block_on(handler.handle(msg_json, message)).err().unwrap();
+        match returned_err {
+            HandleError::SinkError{ reason: _, output: Outputs::Server} => (),
+            _ => panic!("Expected a sink error but got {:?}", returned_err),
+        }
+    }
+}
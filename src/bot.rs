@@ -0,0 +1,232 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::channel;
+use crate::handler::{self, HandleError, Handler, Outputs, Response};
+use crate::message as msg;
+use crate::validate;
+
+/// A bot that runs in the same process as the client. Instead of reading and
+/// writing raw JSON over `Outputs::Bot`, the client calls it directly.
+///
+/// Use `msg::MessageContent` for `State` and `Action` to work with untyped JSON.
+/// Both must serialise to a JSON object, as their fields become the fields of
+/// the message.
+pub trait Bot {
+    type State: DeserializeOwned + Serialize;
+    type Action: DeserializeOwned + Serialize;
+
+    /// Decide on the action to take for the given state.
+    fn on_state(&mut self, state: &Self::State) -> Self::Action;
+
+    /// Called when the server reports an error.
+    fn on_error(&mut self, _error: &msg::MessageContent) {}
+
+    /// Called when the game is over.
+    fn on_game_end(&mut self, _result: &msg::MessageContent) {}
+}
+
+/// Handler that drives a `Bot` and only needs an output towards the server.
+pub struct BotHandler<B> {
+    bot: Mutex<B>,
+    inner: handler::MessageHandler,
+}
+
+impl<B: Bot> BotHandler<B> {
+    pub fn new(bot: B, client_config: handler::ClientConfig) -> Self {
+        BotHandler{
+            bot: Mutex::new(bot),
+            inner: handler::MessageHandler::new(client_config),
+        }
+    }
+
+    /// Check every action of the bot before it is sent to the server. Invalid
+    /// actions are passed to `Bot::on_error` instead.
+    pub fn set_action_validator(&mut self, validator: Box<dyn validate::ActionValidator>) {
+        self.inner.set_action_validator(validator);
+    }
+
+    fn handle_state(&self, state: B::State) -> Result<Response, HandleError> {
+        let mut bot = self.bot.lock().unwrap();
+        let action = bot.on_state(&state);
+        let action_msg = msg::Message::<B::State, B::Action>::Action(action).into_untyped()?;
+
+        if let msg::Message::Action(content) = &action_msg {
+            if let Err(error) = self.inner.check_action(content) {
+                bot.on_error(&error);
+                return Ok(Response::Empty);
+            }
+        }
+        let action_json = msg::serialize_message(action_msg)?;
+        self.inner.send(action_json, &Outputs::Server)?;
+        Ok(Response::Action)
+    }
+}
+
+impl<B: Bot> Handler<B::State, B::Action> for BotHandler<B> {
+    fn handle(&self, json: String, msg_type: msg::Message<B::State, B::Action>) -> Result<Response,HandleError> {
+        match msg_type {
+            msg::Message::State(state) => self.handle_state(state),
+            msg::Message::Error(error) => {
+                self.bot.lock().unwrap().on_error(&error);
+                Ok(Response::Empty)
+            },
+            msg::Message::GameEnd(result) => {
+                self.bot.lock().unwrap().on_game_end(&result);
+                Ok(Response::Empty)
+            },
+            _ => self.inner.handle(json, msg_type.into_untyped()?),
+        }
+    }
+
+    fn add_output(&mut self, output_type: Outputs, output: channel::OutputSender) {
+        self.inner.add_output(output_type, output);
+    }
+
+    fn client_config(&self) -> Option<&handler::ClientConfig> {
+        Handler::client_config(&self.inner)
+    }
+
+    fn inject(&self, json: String, output: Outputs) -> Result<Response,HandleError> {
+        self.inner.inject(json, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn default_client_config() -> handler::ClientConfig {
+        handler::ClientConfig::builder()
+            .game("test_game")
+            .name("test_bot")
+            .build()
+            .unwrap()
+    }
+
+    #[derive(Default)]
+    struct Calls {
+        errors: Vec<msg::MessageContent>,
+        game_ends: Vec<msg::MessageContent>,
+    }
+
+    // Moves to the turn it was given, and records every other callback.
+    struct TurnBot {
+        calls: Arc<Mutex<Calls>>,
+    }
+
+    impl Bot for TurnBot {
+        type State = msg::MessageContent;
+        type Action = msg::MessageContent;
+
+        fn on_state(&mut self, state: &msg::MessageContent) -> msg::MessageContent {
+            msg::MessageContent{
+                content: json!({"move": state.content["turn"]}),
+            }
+        }
+
+        fn on_error(&mut self, error: &msg::MessageContent) {
+            self.calls.lock().unwrap().errors.push(error.clone());
+        }
+
+        fn on_game_end(&mut self, result: &msg::MessageContent) {
+            self.calls.lock().unwrap().game_ends.push(result.clone());
+        }
+    }
+
+    fn bot_handler() -> (BotHandler<TurnBot>, Arc<Mutex<Calls>>, crossbeam_channel::Receiver<String>) {
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let mut handler = BotHandler::new(TurnBot{ calls: calls.clone() }, default_client_config());
+
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        handler.add_output_channel(Outputs::Server, sender);
+        (handler, calls, receiver)
+    }
+
+    fn handle_json(handler: &BotHandler<TurnBot>, json: &str) -> Result<Response, HandleError> {
+        let message = msg::deserialize_message(json).unwrap();
+        handler.handle(json.to_string(), message)
+    }
+
+    #[test]
+    fn state_is_answered_with_action_to_server() {
+        let (handler, _, receiver) = bot_handler();
+
+        handle_json(&handler, r#"{"type": "State", "turn": 3}"#).unwrap();
+
+        assert_eq!(receiver.recv().unwrap(), r#"{"type":"Action","move":3}"#);
+    }
+
+    #[test]
+    fn connected_still_registers() {
+        let (handler, _, receiver) = bot_handler();
+
+        handle_json(&handler, r#"{"type": "Connected"}"#).unwrap();
+
+        assert_eq!(
+            receiver.recv().unwrap(),
+            r#"{"type":"Register","clientType":"bot","game":"test_game","name":"test_bot"}"#);
+    }
+
+    #[test]
+    fn error_and_game_end_are_passed_to_bot() {
+        let (handler, calls, receiver) = bot_handler();
+
+        handle_json(&handler, r#"{"type": "Error", "message": "invalid"}"#).unwrap();
+        handle_json(&handler, r#"{"type": "GameEnd", "winner": "test_bot"}"#).unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.errors[0].content["message"], "invalid");
+        assert_eq!(calls.game_ends[0].content["winner"], "test_bot");
+        assert!(receiver.try_recv().is_err(), "nothing should be sent to the server");
+    }
+
+    #[test]
+    fn invalid_action_is_passed_to_bot_as_error() {
+        let (mut handler, calls, receiver) = bot_handler();
+        handler.set_action_validator(Box::new(|_: &serde_json::Value| Err("not your turn".to_string())));
+
+        handle_json(&handler, r#"{"type": "State", "turn": 3}"#).unwrap();
+
+        assert_eq!(calls.lock().unwrap().errors[0].content["message"], "invalid action: not your turn");
+        assert!(receiver.try_recv().is_err(), "nothing should be sent to the server");
+    }
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct Position {
+        x: i32,
+    }
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct Step {
+        to: i32,
+    }
+
+    struct StepBot;
+
+    impl Bot for StepBot {
+        type State = Position;
+        type Action = Step;
+
+        fn on_state(&mut self, state: &Position) -> Step {
+            Step{ to: state.x + 1 }
+        }
+    }
+
+    #[test]
+    fn typed_bot_receives_typed_state() {
+        let mut handler = BotHandler::new(StepBot, default_client_config());
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        handler.add_output_channel(Outputs::Server, sender);
+
+        let json = r#"{"type": "State", "x": 41}"#;
+        let message = msg::deserialize_typed_message(json).unwrap();
+        handler.handle(json.to_string(), message).unwrap();
+
+        assert_eq!(receiver.recv().unwrap(), r#"{"type":"Action","to":42}"#);
+    }
+}
@@ -0,0 +1,344 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crossbeam_channel::{Receiver, SendError, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+
+/// What an `OutputSender` does when its bounded channel is full.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait until the consumer makes room.
+    Block,
+    /// Throw away the oldest queued message to make room for the new one.
+    /// The sender keeps a receiver for that, so a send doesn't fail once the
+    /// consumer is gone, it keeps dropping the oldest message instead.
+    DropOldest,
+    /// Throw away the message that is being sent.
+    DropNewest,
+    /// Close the channel, the consumer sees a disconnect once it drained the queue.
+    Disconnect,
+}
+
+#[derive(Debug,Clone)]
+pub struct ChannelConfig {
+    /// Zero makes a rendezvous channel with `Block`, the other policies
+    /// need room for a message and use a capacity of 1.
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig{
+            capacity: 64,
+            policy: OverflowPolicy::Block,
+        }
+    }
+}
+
+/// Counters describing how often an overflow policy kicked in.
+#[derive(Debug,Default)]
+pub struct ChannelStats {
+    sent: AtomicU64,
+    blocked: AtomicU64,
+    dropped_oldest: AtomicU64,
+    dropped_newest: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+impl ChannelStats {
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_oldest(&self) -> u64 {
+        self.dropped_oldest.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_newest(&self) -> u64 {
+        self.dropped_newest.load(Ordering::Relaxed)
+    }
+
+    pub fn disconnected(&self) -> u64 {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Sending half of a channel that applies an `OverflowPolicy` when it is full.
+pub struct OutputSender {
+    sender: Mutex<Option<Sender<String>>>,
+    // Only kept for `DropOldest`, holding on to a receiver means a dropped
+    // consumer is no longer noticed by the sender.
+    receiver: Option<Receiver<String>>,
+    policy: OverflowPolicy,
+    stats: Arc<ChannelStats>,
+}
+
+/// Create a bounded channel that applies the configured overflow policy.
+pub fn bounded(config: &ChannelConfig) -> (OutputSender, Receiver<String>) {
+    // A zero capacity channel is always full without a waiting receiver,
+    // `DropOldest` would never make room.
+    let capacity = match config.policy {
+        OverflowPolicy::Block => config.capacity,
+        _ => config.capacity.max(1),
+    };
+    let (sender, receiver) = crossbeam_channel::bounded(capacity);
+    let kept_receiver = match config.policy {
+        OverflowPolicy::DropOldest => Some(receiver.clone()),
+        _ => None,
+    };
+
+    let output = OutputSender{
+        sender: Mutex::new(Some(sender)),
+        receiver: kept_receiver,
+        policy: config.policy,
+        stats: Arc::new(ChannelStats::default()),
+    };
+    (output, receiver)
+}
+
+impl From<Sender<String>> for OutputSender {
+    fn from(sender: Sender<String>) -> Self {
+        OutputSender{
+            sender: Mutex::new(Some(sender)),
+            receiver: None,
+            policy: OverflowPolicy::Block,
+            stats: Arc::new(ChannelStats::default()),
+        }
+    }
+}
+
+impl OutputSender {
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub fn stats(&self) -> Arc<ChannelStats> {
+        self.stats.clone()
+    }
+
+    pub fn send(&self, m: String) -> Result<(), SendError<String>> {
+        let mut guard = self.sender.lock().unwrap();
+        let sender = match guard.as_ref() {
+            Some(sender) => sender,
+            None => return Err(SendError(m)),
+        };
+
+        let m = match sender.try_send(m) {
+            Ok(()) => {
+                ChannelStats::incr(&self.stats.sent);
+                return Ok(());
+            },
+            Err(TrySendError::Disconnected(m)) => return Err(SendError(m)),
+            Err(TrySendError::Full(m)) => m,
+        };
+
+        match self.policy {
+            OverflowPolicy::Block => {
+                ChannelStats::incr(&self.stats.blocked);
+                // Other senders shouldn't wait on the lock while this one waits for room.
+                let sender = sender.clone();
+                drop(guard);
+                sender.send(m)?;
+                ChannelStats::incr(&self.stats.sent);
+                Ok(())
+            },
+            OverflowPolicy::DropOldest => self.send_dropping_oldest(sender, m),
+            OverflowPolicy::DropNewest => {
+                ChannelStats::incr(&self.stats.dropped_newest);
+                Ok(())
+            },
+            OverflowPolicy::Disconnect => {
+                ChannelStats::incr(&self.stats.disconnected);
+                *guard = None;
+                Err(SendError(m))
+            },
+        }
+    }
+
+    fn send_dropping_oldest(&self, sender: &Sender<String>, mut m: String) -> Result<(), SendError<String>> {
+        let receiver = self.receiver.as_ref()
+            .expect("a DropOldest sender always keeps a receiver");
+        loop {
+            if receiver.try_recv().is_ok() {
+                ChannelStats::incr(&self.stats.dropped_oldest);
+            }
+            match sender.try_send(m) {
+                Ok(()) => {
+                    ChannelStats::incr(&self.stats.sent);
+                    return Ok(());
+                },
+                Err(TrySendError::Disconnected(returned)) => return Err(SendError(returned)),
+                // The consumer can't race us to fill the queue, but another
+                // producer on a cloned sender could, so simply try again.
+                Err(TrySendError::Full(returned)) => m = returned,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn config(capacity: usize, policy: OverflowPolicy) -> ChannelConfig {
+        ChannelConfig{
+            capacity,
+            policy,
+        }
+    }
+
+    fn send_all(sender: &OutputSender, messages: &[&str]) -> Vec<Result<(), SendError<String>>> {
+        messages.iter()
+            .map(|m| sender.send(m.to_string()))
+            .collect()
+    }
+
+    fn drain(receiver: &Receiver<String>) -> Vec<String> {
+        receiver.try_iter().collect()
+    }
+
+    // Consumer that only starts reading after a while, and reads slowly.
+    fn slow_consumer(receiver: Receiver<String>, expected: usize) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut received = Vec::new();
+            while received.len() < expected {
+                received.push(receiver.recv().unwrap());
+                thread::sleep(Duration::from_millis(5));
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn block_waits_for_slow_consumer_and_loses_nothing() {
+        let (sender, receiver) = bounded(&config(1, OverflowPolicy::Block));
+        let consumer = slow_consumer(receiver, 3);
+
+        let results = send_all(&sender, &["1", "2", "3"]);
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        assert_eq!(consumer.join().unwrap(), vec!["1", "2", "3"]);
+        let stats = sender.stats();
+        assert_eq!(stats.sent(), 3);
+        assert!(stats.blocked() >= 1, "expected at least one blocked send");
+    }
+
+    #[test]
+    fn drop_oldest_keeps_most_recent_messages() {
+        let (sender, receiver) = bounded(&config(2, OverflowPolicy::DropOldest));
+
+        let results = send_all(&sender, &["1", "2", "3", "4"]);
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        assert_eq!(drain(&receiver), vec!["3", "4"]);
+        assert_eq!(sender.stats().dropped_oldest(), 2);
+        assert_eq!(sender.stats().sent(), 4);
+    }
+
+    #[test]
+    fn drop_oldest_does_not_notice_a_dropped_consumer() {
+        let (sender, receiver) = bounded(&config(1, OverflowPolicy::DropOldest));
+        drop(receiver);
+
+        assert!(send_all(&sender, &["1", "2"]).iter().all(|r| r.is_ok()));
+        assert_eq!(sender.stats().dropped_oldest(), 1);
+    }
+
+    #[test]
+    fn drop_newest_keeps_queued_messages() {
+        let (sender, receiver) = bounded(&config(2, OverflowPolicy::DropNewest));
+
+        let results = send_all(&sender, &["1", "2", "3", "4"]);
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        assert_eq!(drain(&receiver), vec!["1", "2"]);
+        assert_eq!(sender.stats().dropped_newest(), 2);
+        assert_eq!(sender.stats().sent(), 2);
+    }
+
+    #[test]
+    fn disconnect_closes_channel_when_full() {
+        let (sender, receiver) = bounded(&config(2, OverflowPolicy::Disconnect));
+
+        let results = send_all(&sender, &["1", "2", "3", "4"]);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        assert_eq!(results[2], Err(SendError("3".to_string())));
+        assert_eq!(results[3], Err(SendError("4".to_string())));
+
+        assert_eq!(receiver.recv().unwrap(), "1");
+        assert_eq!(receiver.recv().unwrap(), "2");
+        assert!(receiver.recv().is_err(), "expected the channel to be disconnected");
+        assert_eq!(sender.stats().disconnected(), 1);
+    }
+
+    #[test]
+    fn slow_consumer_with_drop_newest_receives_subset() {
+        let (sender, receiver) = bounded(&config(1, OverflowPolicy::DropNewest));
+        let consumer = slow_consumer(receiver, 1);
+
+        let results = send_all(&sender, &["1", "2", "3"]);
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        assert_eq!(consumer.join().unwrap(), vec!["1"]);
+        assert_eq!(sender.stats().dropped_newest(), 2);
+    }
+
+    #[test]
+    fn zero_capacity_holds_one_message_when_dropping() {
+        let (oldest, oldest_receiver) = bounded(&config(0, OverflowPolicy::DropOldest));
+        assert!(send_all(&oldest, &["1", "2"]).iter().all(|r| r.is_ok()));
+        assert_eq!(drain(&oldest_receiver), vec!["2"]);
+
+        let (newest, newest_receiver) = bounded(&config(0, OverflowPolicy::DropNewest));
+        assert!(send_all(&newest, &["1", "2"]).iter().all(|r| r.is_ok()));
+        assert_eq!(drain(&newest_receiver), vec!["1"]);
+    }
+
+    #[test]
+    fn blocked_sender_does_not_hold_the_lock() {
+        let (sender, receiver) = bounded(&config(1, OverflowPolicy::Block));
+        let sender = Arc::new(sender);
+        sender.send("1".to_string()).unwrap();
+
+        let blocked = sender.clone();
+        let blocked = thread::spawn(move || blocked.send("2".to_string()));
+        while sender.stats().blocked() == 0 {
+            thread::yield_now();
+        }
+        // The lock is released while the other send waits for room.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while sender.sender.try_lock().is_err() {
+            assert!(std::time::Instant::now() < deadline, "the blocked sender holds the lock");
+            thread::yield_now();
+        }
+
+        assert_eq!(receiver.recv().unwrap(), "1");
+        blocked.join().unwrap().unwrap();
+        assert_eq!(receiver.recv().unwrap(), "2");
+    }
+
+    #[test]
+    fn plain_sender_blocks_by_default() {
+        let (plain, receiver) = crossbeam_channel::bounded(1);
+        let sender = OutputSender::from(plain);
+        assert_eq!(sender.policy(), OverflowPolicy::Block);
+
+        sender.send("1".to_string()).unwrap();
+        assert_eq!(drain(&receiver), vec!["1"]);
+    }
+}
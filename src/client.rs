@@ -1,30 +1,183 @@
-use crate::handler;
+use crate::bot;
+use crate::channel;
+use crate::clock::{self, Clock};
+use crate::handler::{self, Handler};
+use crate::heartbeat::{self, HeartbeatConfig, Liveness, OnDeadPeer};
 use crate::message as msg;
-use crossbeam_channel::{select,unbounded};
+use crate::metrics;
+use crate::transport::{self, Transport};
+use crossbeam_channel::select;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::field;
 
-pub struct Client {
-    handler: Box<dyn handler::Handler>,
-    inc_server_chan: crossbeam_channel::Receiver<String>,
-    inc_bot_chan: crossbeam_channel::Receiver<String>,
+/// The server or the bot side of a client.
+struct Peer {
+    // Swapped on a reconnect.
+    incoming: Mutex<crossbeam_channel::Receiver<String>>,
+    // Only set when the client owns the transport, to reconnect it.
+    link: Option<Link>,
+    heartbeat: Option<(HeartbeatConfig, Liveness)>,
+}
+
+struct Link {
+    transport: Arc<dyn Transport>,
+    config: channel::ChannelConfig,
+}
+
+/// Writer threads of the transports a client attached. Dropped after the
+/// handler, which holds their senders, so messages that are still queued go
+/// out before the client is gone.
+#[derive(Default)]
+struct Writers(Mutex<Vec<thread::JoinHandle<()>>>);
+
+impl Writers {
+    fn add(&self, writer: thread::JoinHandle<()>) {
+        self.0.lock().unwrap().push(writer);
+    }
+}
+
+impl Drop for Writers {
+    fn drop(&mut self) {
+        for writer in self.0.get_mut().unwrap().drain(..) {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Peer {
+    fn new(incoming: crossbeam_channel::Receiver<String>) -> Self {
+        Peer{ incoming: Mutex::new(incoming), link: None, heartbeat: None }
+    }
+}
+
+/// Client for a game whose `State` and `Action` are of type `S` and `A`.
+pub struct Client<S = msg::MessageContent, A = msg::MessageContent> {
+    // Locked to swap an output on a reconnect.
+    handler: Mutex<Box<dyn handler::Handler<S, A>>>,
+    // Must stay after `handler`, see `Writers`.
+    writers: Writers,
+    server: Peer,
+    bot: Peer,
     started: Mutex<bool>,
+    span: tracing::Span,
+    recorder: Arc<dyn metrics::Recorder>,
+    clock: Arc<dyn Clock>,
+    tick_interval: Option<Duration>,
+    // When the last `State` from the server was received, to measure how long
+    // the bot takes to answer with an `Action`.
+    state_received: Mutex<Option<Instant>>,
 }
 
-impl Client {
-    fn new(
-        handler: Box<dyn handler::Handler>,
+impl<S: DeserializeOwned, A: DeserializeOwned> Client<S, A> {
+    pub fn new(
+        handler: Box<dyn handler::Handler<S, A>>,
         inc_server_chan: crossbeam_channel::Receiver<String>,
         inc_bot_chan: crossbeam_channel::Receiver<String>) -> Self {
+        let span = match handler.client_config() {
+            Some(config) => tracing::info_span!("client", game = %config.game(), name = %config.name(), id = field::Empty),
+            None => tracing::info_span!("client", game = field::Empty, name = field::Empty, id = field::Empty),
+        };
+
         Client{
-            handler: handler,
-            inc_server_chan: inc_server_chan,
-            inc_bot_chan: inc_bot_chan,
+            handler: Mutex::new(handler),
+            writers: Writers::default(),
+            server: Peer::new(inc_server_chan),
+            bot: Peer::new(inc_bot_chan),
             started: Mutex::new(false),
+            span: span,
+            recorder: Arc::new(metrics::NoopRecorder),
+            clock: Arc::new(clock::SystemClock),
+            tick_interval: None,
+            state_received: Mutex::new(None),
         }
     }
 
-    fn start(&self) -> Result<(),crossbeam_channel::RecvError>{
+    /// Report message counts, handle errors and bot latency to `recorder`.
+    pub fn set_recorder(&mut self, recorder: Arc<dyn metrics::Recorder>) {
+        self.recorder = recorder;
+    }
+
+    /// Use `clock` instead of the wall clock for everything time related.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Call `Handler::tick` every `interval` while the client runs.
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        self.tick_interval = Some(interval);
+    }
+
+    /// Ping the server every `config.interval` and act on
+    /// `config.on_dead_peer` when it has been silent for `config.timeout`.
+    /// Only a client made `with_transports` can reconnect, others shut down.
+    pub fn set_heartbeat(&mut self, config: HeartbeatConfig) {
+        let liveness = Liveness::new(config.timeout);
+        self.server.heartbeat = Some((config, liveness));
+    }
+
+    /// Health check the bot the same way: it should answer every `Ping` with
+    /// a `Pong`, or send anything else, within `config.timeout`. An
+    /// unresponsive bot is restarted with `OnDeadPeer::Reconnect`.
+    pub fn set_bot_heartbeat(&mut self, config: HeartbeatConfig) {
+        let liveness = Liveness::new(config.timeout);
+        self.bot.heartbeat = Some((config, liveness));
+    }
+
+    /// Create a client with incoming channels bounded by `config`. Returns the
+    /// client together with the senders for the server and the bot side.
+    pub fn with_bounded_inputs(
+        handler: Box<dyn handler::Handler<S, A>>,
+        config: &channel::ChannelConfig) -> (Self, channel::OutputSender, channel::OutputSender) {
+        let (server_sender, inc_server_chan) = channel::bounded(config);
+        let (bot_sender, inc_bot_chan) = channel::bounded(config);
+        (Client::new(handler, inc_server_chan, inc_bot_chan), server_sender, bot_sender)
+    }
+
+    /// Create a client that talks to the server and the bot over transports,
+    /// both are connected first.
+    pub fn with_transports(
+        mut handler: Box<dyn handler::Handler<S, A>>,
+        server: Arc<dyn Transport>,
+        bot: Arc<dyn Transport>,
+        config: &channel::ChannelConfig) -> Result<Self, transport::TransportError> {
+        server.connect()?;
+        bot.connect()?;
+
+        let server_link = Link{ transport: server.clone(), config: config.clone() };
+        let bot_link = Link{ transport: bot.clone(), config: config.clone() };
+        let server = transport::attach(server, config);
+        let bot = transport::attach(bot, config);
+        handler.add_output(handler::Outputs::Server, server.outgoing);
+        handler.add_output(handler::Outputs::Bot, bot.outgoing);
+        let mut client = Client::new(handler, server.incoming, bot.incoming);
+        client.writers.add(server.writer);
+        client.writers.add(bot.writer);
+        client.server.link = Some(server_link);
+        client.bot.link = Some(bot_link);
+        Ok(client)
+    }
+
+    /// Create a client that drives an in-process `Bot`, there is no bot
+    /// channel, the only output is towards the server.
+    pub fn with_bot<B>(
+        bot: B,
+        client_config: handler::ClientConfig,
+        inc_server_chan: crossbeam_channel::Receiver<String>,
+        server_output: channel::OutputSender) -> Self
+            where B: bot::Bot<State = S, Action = A> + 'static, S: Serialize + 'static, A: Serialize + 'static {
+        let mut handler = bot::BotHandler::new(bot, client_config);
+        handler.add_output(handler::Outputs::Server, server_output);
+        Client::new(Box::new(handler), inc_server_chan, crossbeam_channel::never())
+    }
+
+    /// Handle messages until a channel closes, or until a heartbeat gives up
+    /// on the server or the bot which returns `Ok`.
+    pub fn start(&self) -> Result<(),crossbeam_channel::RecvError>{
         // Make sure the start function is only executed once.
         {
             let mut started = self.started.lock().unwrap();
@@ -34,77 +187,839 @@ impl Client {
             *started = true;
         }
 
+        for peer in [&self.server, &self.bot] {
+            if let Some((_, liveness)) = &peer.heartbeat {
+                liveness.seen(self.clock.now());
+            }
+        }
+        let mut ticker = self.ticker();
+        let mut wake = crossbeam_channel::never();
+        let mut server_heartbeat = self.heartbeat_timer(&self.server);
+        let mut bot_heartbeat = self.heartbeat_timer(&self.bot);
         loop {
-            select!{
-                recv(self.inc_server_chan) -> msg => self.handle(msg)?,
-                recv(self.inc_bot_chan) -> msg => self.handle(msg)?,
+            // The cloned receivers are dropped before a heartbeat runs, so a
+            // reconnect can disconnect the old reader thread.
+            let due = {
+                let inc_server_chan = self.server.incoming.lock().unwrap().clone();
+                let inc_bot_chan = self.bot.incoming.lock().unwrap().clone();
+                select!{
+                    recv(inc_server_chan) -> msg => {
+                        self.handle(msg, handler::Outputs::Server)?;
+                        None
+                    },
+                    recv(inc_bot_chan) -> msg => {
+                        self.handle(msg, handler::Outputs::Bot)?;
+                        None
+                    },
+                    recv(ticker) -> _ => {
+                        self.tick();
+                        ticker = self.ticker();
+                        None
+                    },
+                    recv(wake) -> _ => {
+                        self.tick();
+                        None
+                    },
+                    recv(server_heartbeat) -> _ => Some(handler::Outputs::Server),
+                    recv(bot_heartbeat) -> _ => Some(handler::Outputs::Bot),
+                }
             };
+
+            if let Some(output) = due {
+                if !self.heartbeat(output.clone()) {
+                    return Ok(());
+                }
+                match output {
+                    handler::Outputs::Server => server_heartbeat = self.heartbeat_timer(&self.server),
+                    _ => bot_heartbeat = self.heartbeat_timer(&self.bot),
+                }
+            }
+            wake = self.wake();
+        }
+    }
+
+    fn peer(&self, output: &handler::Outputs) -> Option<&Peer> {
+        match output {
+            handler::Outputs::Server => Some(&self.server),
+            handler::Outputs::Bot => Some(&self.bot),
+            handler::Outputs::Viewer => None,
+        }
+    }
+
+    fn heartbeat_timer(&self, peer: &Peer) -> crossbeam_channel::Receiver<Instant> {
+        match &peer.heartbeat {
+            Some((config, _)) => self.clock.after(config.interval),
+            None => crossbeam_channel::never(),
+        }
+    }
+
+    /// Ping a live peer or act on a dead one. Returns whether the client
+    /// keeps running.
+    fn heartbeat(&self, output: handler::Outputs) -> bool {
+        let _client = self.span.enter();
+        let (config, liveness) = match self.peer(&output).and_then(|peer| peer.heartbeat.as_ref()) {
+            Some(heartbeat) => heartbeat,
+            None => return true,
+        };
+
+        let now = self.clock.now();
+        if liveness.is_alive(now) {
+            match self.handler.lock().unwrap().inject(heartbeat::ping(), output.clone()) {
+                Ok(_) => self.record_generated("Ping"),
+                Err(e) => tracing::warn!(peer = ?output, error = %e, "send heartbeat"),
+            }
+            return true;
+        }
+
+        let peer = format!("{:?}", output);
+        self.recorder.increment_counter(metrics::UNRESPONSIVE_PEERS_TOTAL, &[("peer", &peer)]);
+        tracing::warn!(%peer, timeout = ?config.timeout, action = ?config.on_dead_peer, "peer stopped responding");
+        if config.on_dead_peer == OnDeadPeer::Reconnect && self.reconnect(output) {
+            liveness.seen(now);
+            return true;
         }
+        false
     }
 
-    fn handle(&self, channel_output: Result<String, crossbeam_channel::RecvError>)
+    /// Connect a peer's transport again and route over the new connection.
+    /// For a bot process that is a restart.
+    fn reconnect(&self, output: handler::Outputs) -> bool {
+        let peer = match self.peer(&output) {
+            Some(peer) => peer,
+            None => return false,
+        };
+        let link = match &peer.link {
+            Some(link) => link,
+            None => return false,
+        };
+
+        if let Err(e) = link.transport.close() {
+            tracing::debug!(peer = ?output, error = %e, "close dead transport");
+        }
+        // Disconnect the old reader thread so it stops instead of reading
+        // from the new connection.
+        *peer.incoming.lock().unwrap() = crossbeam_channel::never();
+        if let Err(e) = link.transport.connect() {
+            tracing::warn!(peer = ?output, error = %e, "reconnect");
+            return false;
+        }
+        let attached = transport::attach(link.transport.clone(), &link.config);
+        self.handler.lock().unwrap().add_output(output.clone(), attached.outgoing);
+        *peer.incoming.lock().unwrap() = attached.incoming;
+        self.writers.add(attached.writer);
+        tracing::info!(peer = ?output, "reconnected");
+        true
+    }
+
+    fn ticker(&self) -> crossbeam_channel::Receiver<Instant> {
+        match self.tick_interval {
+            Some(interval) => self.clock.after(interval),
+            None => crossbeam_channel::never(),
+        }
+    }
+
+    /// Fires when the handler asked for a tick, like to release messages it
+    /// held back.
+    fn wake(&self) -> crossbeam_channel::Receiver<Instant> {
+        match self.handler.lock().unwrap().next_tick() {
+            Some(due) => self.clock.after(due),
+            None => crossbeam_channel::never(),
+        }
+    }
+
+    fn tick(&self) {
+        let _client = self.span.enter();
+        if let Err(e) = self.handler.lock().unwrap().tick() {
+            self.recorder.increment_counter(metrics::HANDLE_ERRORS_TOTAL, &[("error", e.kind())]);
+            tracing::warn!(error = %e, "tick");
+        }
+    }
+
+    fn handle(&self, channel_output: Result<String, crossbeam_channel::RecvError>, source: handler::Outputs)
             -> Result<(), crossbeam_channel::RecvError> {
         let message_string = channel_output?;
-        let message = msg::deserialize_message(&message_string).unwrap();
-        let response = self.handler.handle(message_string,message);
+        if let Some((_, liveness)) = self.peer(&source).and_then(|peer| peer.heartbeat.as_ref()) {
+            liveness.seen(self.clock.now());
+        }
+
+        let _client = self.span.enter();
+        let span = tracing::debug_span!("message",
+            from = ?source,
+            msg_type = field::Empty,
+            size = message_string.len(),
+            route = field::Empty);
+        let _message = span.enter();
+
+        let message = match msg::deserialize_typed_message(&message_string) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::warn!(error = %e, "dropping message");
+                return Ok(());
+            },
+        };
+        span.record("msg_type", message.type_name());
+        self.record_received(&message, &source);
+
+        let handler = self.handler.lock().unwrap();
+        let result = match message {
+            msg::Message::Ping(ping) => heartbeat::answer(ping, source, |pong, output| handler.inject(pong, output))
+                .inspect(|_| self.record_generated("Pong")),
+            message => handler.handle(message_string, message),
+        };
+        match result {
+            Ok(handler::Response::SetID(id)) => {
+                self.span.record("id", id);
+                tracing::info!("registered");
+            },
+            Ok(handler::Response::Action) => {
+                self.record_generated("Action");
+                self.record_latency();
+                tracing::debug!("handled");
+            },
+            Ok(handler::Response::Empty) => tracing::debug!("handled"),
+            Err(e) => {
+                self.recorder.increment_counter(metrics::HANDLE_ERRORS_TOTAL, &[("error", e.kind())]);
+                tracing::warn!(error = %e, "handle message");
+            },
+        }
         Ok(())
     }
+
+    fn record_received(&self, message: &msg::Message<S, A>, source: &handler::Outputs) {
+        let from = format!("{:?}", source);
+        self.recorder.increment_counter(metrics::MESSAGES_TOTAL, &[("type", message.type_name()), ("from", &from)]);
+
+        match (message, source) {
+            (msg::Message::State(_), handler::Outputs::Server) => *self.state_received.lock().unwrap() = Some(self.clock.now()),
+            (msg::Message::Action(_), handler::Outputs::Bot) => self.record_latency(),
+            _ => (),
+        }
+    }
+
+    /// Count a message the client or its handler made itself, like a
+    /// heartbeat or the action of an in-process bot.
+    fn record_generated(&self, message_type: &str) {
+        self.recorder.increment_counter(metrics::MESSAGES_TOTAL, &[("type", message_type), ("from", "Client")]);
+    }
+
+    /// Time since the last `State`, now that the bot answered it.
+    fn record_latency(&self) {
+        if let Some(received) = self.state_received.lock().unwrap().take() {
+            let latency = (self.clock.now() - received).as_secs_f64();
+            self.recorder.record_histogram(metrics::STATE_TO_ACTION_SECONDS, &[], latency);
+        }
+    }
+}
+
+impl Client {
+    /// Create a client that watches a game, like for a dashboard. What a bot
+    /// would get, every `State` up to the `GameEnd`, goes to `viewer_output`.
+    /// `client_config` should have `ClientType::Viewer`, a viewer has no bot
+    /// and never sends actions.
+    pub fn with_viewer(
+        client_config: handler::ClientConfig,
+        inc_server_chan: crossbeam_channel::Receiver<String>,
+        server_output: channel::OutputSender,
+        viewer_output: channel::OutputSender) -> Client {
+        let mut handler = handler::MessageHandler::new(client_config);
+        handler.add_output(handler::Outputs::Server, server_output);
+        handler.add_output(handler::Outputs::Viewer, viewer_output);
+        Client::new(Box::new(handler), inc_server_chan, crossbeam_channel::never())
+    }
 }
 
 #[cfg(test)]
 mod client {
     use super::*;
     use std::thread;
-    use crate::handler::*;
 
     fn default_client_config() -> handler::ClientConfig {
-        handler::ClientConfig{
-            clientType: "bot".to_string(),
-            game: "test_game".to_string(),
-            name: "test_bot".to_string(),
+        handler::ClientConfig::builder()
+            .game("test_game")
+            .name("test_bot")
+            .build()
+            .unwrap()
+    }
+
+    struct SyncHarness;
+
+    impl suite::Harness for SyncHarness {
+        fn exchange(msg_to_send: &str, output_type: handler::Outputs) -> String {
+            create_client_and_handle_message(msg_to_send, output_type)
         }
     }
 
-	#[test]
-    fn received_connected_message_should_respond_with_register_message() {
-        let output_destination = handler::Outputs::Server;
-        let incomming_message = r#"{"type": "Connected"}"#;
-        let expected_outbound_message = r#"{"type":"Register","clientType":"bot","game":"test_game","name":"test_bot"}"#;
+    suite::routing_tests!(SyncHarness);
 
-        create_client_and_handle_message(incomming_message, output_destination, expected_outbound_message)
+    #[test]
+    fn bounded_client_drops_newest_state_for_slow_bot() {
+        let config = channel::ChannelConfig{
+            capacity: 1,
+            policy: channel::OverflowPolicy::DropNewest,
+        };
+        let (bot_output, bot_rec) = channel::bounded(&config);
+        let bot_stats = bot_output.stats();
+        let (inputs_snd, inputs_rec) = crossbeam_channel::bounded(1);
+
+        thread::spawn(move || {
+            let mut handler = handler::MessageHandler::new(default_client_config());
+            handler.add_output(handler::Outputs::Bot, bot_output);
+
+            let input_config = channel::ChannelConfig::default();
+            let (client, svr_inc_snd, bot_inc_snd) = Client::with_bounded_inputs(Box::new(handler), &input_config);
+            inputs_snd.send((svr_inc_snd, bot_inc_snd)).unwrap();
+            client.start()
+        });
+        let (svr_inc_snd, _bot_inc_snd) = inputs_rec.recv().unwrap();
+
+        // The bot doesn't read until all states have been sent.
+        for i in 0..3 {
+            svr_inc_snd.send(format!(r#"{{"type": "State", "turn": {}}}"#, i)).unwrap();
+        }
+        while bot_stats.sent() + bot_stats.dropped_newest() < 3 {
+            thread::yield_now();
+        }
+
+        assert_eq!(bot_rec.recv().unwrap(), r#"{"type": "State", "turn": 0}"#);
+        assert_eq!(bot_stats.dropped_newest(), 2);
     }
 
     #[test]
-    fn receive_state_message_and_pass_on_to_bot() {
-        let output_destination = handler::Outputs::Bot;
-        let incomming_message = r#"{"type": "State", "other": "fields"}"#;
-        let expected_outbound_message = r#"{"type": "State", "other": "fields"}"#;
+    fn client_with_bot_answers_state_without_bot_channel() {
+        struct PassBot;
+
+        impl bot::Bot for PassBot {
+            type State = msg::MessageContent;
+            type Action = msg::MessageContent;
+
+            fn on_state(&mut self, _state: &msg::MessageContent) -> msg::MessageContent {
+                msg::MessageContent{ content: serde_json::json!({"pass": true}) }
+            }
+        }
+
+        let (svr_inc_snd, svr_inc_rec) = crossbeam_channel::bounded(1);
+        let (output_snd, output_rec) = crossbeam_channel::bounded(1);
 
-        create_client_and_handle_message(incomming_message, output_destination, expected_outbound_message)
+        thread::spawn(move || {
+            let client = Client::with_bot(PassBot, default_client_config(), svr_inc_rec, output_snd.into());
+            client.start()
+        });
+
+        svr_inc_snd.send(r#"{"type": "State"}"#.to_string()).unwrap();
+        assert_eq!(output_rec.recv().unwrap(), r#"{"type":"Action","pass":true}"#);
     }
 
     #[test]
-    fn receive_error_message_and_pass_on_to_bot() {
-        let output_destination = handler::Outputs::Bot;
-        let incomming_message = r#"{"type": "Error", "message": "string"}"#;
-        let expected_outbound_message = r#"{"type": "Error", "message": "string"}"#;
+    fn client_with_memory_transports_routes_both_ways() {
+        use crate::transport::memory::MemoryTransport;
 
-        create_client_and_handle_message(incomming_message, output_destination, expected_outbound_message)
+        let (server_end, server) = MemoryTransport::pair();
+        let (bot_end, bot) = MemoryTransport::pair();
+        server.connect().unwrap();
+        bot.connect().unwrap();
+
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(default_client_config()));
+            let client: Client = Client::with_transports(handler, Arc::new(server_end), Arc::new(bot_end),
+                &channel::ChannelConfig::default()).unwrap();
+            client.start()
+        });
+
+        server.send(r#"{"type":"State","turn":1}"#).unwrap();
+        assert_eq!(bot.receive().unwrap().unwrap(), r#"{"type":"State","turn":1}"#);
+        bot.send(r#"{"type":"Action","move":"up"}"#).unwrap();
+        assert_eq!(server.receive().unwrap().unwrap(), r#"{"type":"Action","move":"up"}"#);
     }
 
     #[test]
-    fn receive_action_message_and_pass_on_to_server() {
-        let output_destination = handler::Outputs::Server;
-        let incomming_message = r#"{"type": "Action", "message": "string"}"#;
-        let expected_outbound_message = r#"{"type": "Action", "message": "string"}"#;
+    fn viewer_client_streams_states() {
+        let (svr_inc_snd, svr_inc_rec) = crossbeam_channel::bounded(1);
+        let (server_snd, server_rec) = crossbeam_channel::unbounded();
+        let (viewer_snd, viewer_rec) = crossbeam_channel::unbounded();
+
+        thread::spawn(move || {
+            let config = handler::ClientConfig::builder()
+                .client_type(crate::client_config::ClientType::Viewer)
+                .game("test_game")
+                .name("test_viewer")
+                .build()
+                .unwrap();
+            let client = Client::with_viewer(config, svr_inc_rec, server_snd.into(), viewer_snd.into());
+            client.start()
+        });
 
-        create_client_and_handle_message(incomming_message, output_destination, expected_outbound_message)
+        svr_inc_snd.send(r#"{"type": "Connected"}"#.to_string()).unwrap();
+        svr_inc_snd.send(r#"{"type": "State", "turn": 1}"#.to_string()).unwrap();
+
+        assert!(server_rec.recv().unwrap().contains(r#""clientType":"viewer""#));
+        assert_eq!(viewer_rec.recv().unwrap(), r#"{"type": "State", "turn": 1}"#);
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn handled_messages_are_traced_with_client_context() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let (output_snd, _output_rec) = crossbeam_channel::unbounded();
+        let mut handler = handler::MessageHandler::new(default_client_config());
+        handler.add_output_channel(handler::Outputs::Bot, output_snd);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), crossbeam_channel::never());
+            client.handle(Ok(r#"{"type": "RegisterSuccess", "id": 7}"#.to_string()), handler::Outputs::Server).unwrap();
+            client.handle(Ok(r#"{"type": "State"}"#.to_string()), handler::Outputs::Server).unwrap();
+            client.handle(Ok("invalid".to_string()), handler::Outputs::Bot).unwrap();
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("client{game=test_game name=test_bot id=7}"), "{}", logs);
+        assert!(logs.contains(r#"message{from=Server size=17 msg_type="State" route=Bot}: "#), "{}", logs);
+        assert!(logs.contains("dropping message"), "{}", logs);
+    }
+
+    #[test]
+    fn handled_messages_are_counted() {
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        let (server_snd, _server_rec) = crossbeam_channel::unbounded();
+        let mut handler = handler::MessageHandler::new(default_client_config());
+        handler.add_output_channel(handler::Outputs::Server, server_snd);
+
+        let mut client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), crossbeam_channel::never());
+        client.set_recorder(recorder.clone());
+
+        // There is no bot output, so the state can't be delivered.
+        client.handle(Ok(r#"{"type": "State"}"#.to_string()), handler::Outputs::Server).unwrap();
+        client.handle(Ok(r#"{"type": "Action"}"#.to_string()), handler::Outputs::Bot).unwrap();
+        client.handle(Ok(r#"{"type": "Action"}"#.to_string()), handler::Outputs::Bot).unwrap();
+
+        assert_eq!(recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "State"), ("from", "Server")]), 1);
+        assert_eq!(recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "Action"), ("from", "Bot")]), 2);
+        assert_eq!(recorder.counter(metrics::HANDLE_ERRORS_TOTAL, &[("error", "UndefinedOutput")]), 1);
+
+        // Only the first action answers the state.
+        let latency = recorder.histogram(metrics::STATE_TO_ACTION_SECONDS, &[]).unwrap();
+        assert_eq!(latency.count, 1);
+    }
+
+    #[test]
+    fn handler_ticks_on_the_clock() {
+        struct Ticks(crossbeam_channel::Sender<()>);
+
+        impl Handler for Ticks {
+            fn handle(&self, _json: String, _message: msg::Message) -> Result<handler::Response, handler::HandleError> {
+                Ok(handler::Response::Empty)
+            }
+
+            fn add_output(&mut self, _output_type: handler::Outputs, _output: channel::OutputSender) {}
+
+            fn tick(&self) -> Result<handler::Response, handler::HandleError> {
+                self.0.send(()).unwrap();
+                Ok(handler::Response::Empty)
+            }
+        }
+
+        let clock = Arc::new(clock::ManualClock::new());
+        let (ticks_snd, ticks) = crossbeam_channel::unbounded();
+        let (_server_snd, server_rec) = crossbeam_channel::unbounded::<String>();
+        let thread_clock = clock.clone();
+        thread::spawn(move || {
+            let mut client: Client = Client::new(Box::new(Ticks(ticks_snd)), server_rec, crossbeam_channel::never());
+            client.set_clock(thread_clock);
+            client.set_tick_interval(Duration::from_secs(1));
+            client.start()
+        });
+
+        for _ in 0..3 {
+            while ticks.try_recv().is_err() {
+                clock.advance(Duration::from_secs(1));
+                thread::yield_now();
+            }
+        }
+    }
+
+    #[test]
+    fn bot_latency_follows_the_clock() {
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        let clock = Arc::new(clock::ManualClock::new());
+        let (server_snd, _server_rec) = crossbeam_channel::unbounded();
+        let (bot_snd, _bot_rec) = crossbeam_channel::unbounded();
+        let mut handler = handler::MessageHandler::new(default_client_config());
+        handler.add_output_channel(handler::Outputs::Server, server_snd);
+        handler.add_output_channel(handler::Outputs::Bot, bot_snd);
+
+        let mut client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), crossbeam_channel::never());
+        client.set_recorder(recorder.clone());
+        client.set_clock(clock.clone());
+
+        client.handle(Ok(r#"{"type": "State"}"#.to_string()), handler::Outputs::Server).unwrap();
+        clock.advance(Duration::from_millis(250));
+        client.handle(Ok(r#"{"type": "Action"}"#.to_string()), handler::Outputs::Bot).unwrap();
+
+        let latency = recorder.histogram(metrics::STATE_TO_ACTION_SECONDS, &[]).unwrap();
+        assert_eq!(latency.sum, 0.25);
+    }
+
+    #[test]
+    fn in_process_bot_latency_is_recorded() {
+        // Takes a quarter of a second to think.
+        struct SlowBot(Arc<clock::ManualClock>);
+
+        impl bot::Bot for SlowBot {
+            type State = msg::MessageContent;
+            type Action = msg::MessageContent;
+
+            fn on_state(&mut self, _state: &msg::MessageContent) -> msg::MessageContent {
+                self.0.advance(Duration::from_millis(250));
+                msg::MessageContent{ content: serde_json::json!({"pass": true}) }
+            }
+        }
+
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        let clock = Arc::new(clock::ManualClock::new());
+        let (output_snd, output_rec) = crossbeam_channel::unbounded();
+        let mut client = Client::with_bot(SlowBot(clock.clone()), default_client_config(), crossbeam_channel::never(), output_snd.into());
+        client.set_recorder(recorder.clone());
+        client.set_clock(clock);
+
+        client.handle(Ok(r#"{"type": "State"}"#.to_string()), handler::Outputs::Server).unwrap();
+        assert_eq!(output_rec.try_recv().unwrap(), r#"{"type":"Action","pass":true}"#);
+        assert_eq!(recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "Action"), ("from", "Client")]), 1);
+        let latency = recorder.histogram(metrics::STATE_TO_ACTION_SECONDS, &[]).unwrap();
+        assert_eq!((latency.count, latency.sum), (1, 0.25));
+    }
+
+    fn heartbeat_config(on_dead_peer: OnDeadPeer) -> HeartbeatConfig {
+        HeartbeatConfig{
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(3),
+            on_dead_peer,
+        }
+    }
+
+    /// Advance the clock until `receiver` has a message.
+    fn advance_until<T>(clock: &clock::ManualClock, receiver: &crossbeam_channel::Receiver<T>) -> T {
+        loop {
+            if let Ok(message) = receiver.try_recv() {
+                return message;
+            }
+            clock.advance(Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn answering_server_stays_alive() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let (svr_inc_snd, svr_inc_rec) = crossbeam_channel::unbounded();
+        let (server_snd, server_rec) = crossbeam_channel::unbounded();
+        let thread_clock = clock.clone();
+        let client = thread::spawn(move || {
+            let mut handler = handler::MessageHandler::new(default_client_config());
+            handler.add_output_channel(handler::Outputs::Server, server_snd);
+            let mut client: Client = Client::new(Box::new(handler), svr_inc_rec, crossbeam_channel::never());
+            client.set_clock(thread_clock);
+            client.set_heartbeat(heartbeat_config(OnDeadPeer::Shutdown));
+            client.start()
+        });
+
+        for _ in 0..10 {
+            assert_eq!(advance_until(&clock, &server_rec), r#"{"type":"Ping"}"#);
+            // The pong shows the server's ping was handled.
+            svr_inc_snd.send(r#"{"type":"Ping"}"#.to_string()).unwrap();
+            assert_eq!(server_rec.recv().unwrap(), r#"{"type":"Pong"}"#);
+        }
+        assert!(!client.is_finished());
+    }
+
+    #[test]
+    fn silent_server_shuts_the_client_down() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let (_svr_inc_snd, svr_inc_rec) = crossbeam_channel::unbounded::<String>();
+        let (server_snd, server_rec) = crossbeam_channel::unbounded();
+        let thread_clock = clock.clone();
+        let client = thread::spawn(move || {
+            let mut handler = handler::MessageHandler::new(default_client_config());
+            handler.add_output_channel(handler::Outputs::Server, server_snd);
+            let mut client: Client = Client::new(Box::new(handler), svr_inc_rec, crossbeam_channel::never());
+            client.set_clock(thread_clock);
+            // Without transports a reconnect isn't possible either.
+            client.set_heartbeat(heartbeat_config(OnDeadPeer::Reconnect));
+            client.start()
+        });
+
+        while !client.is_finished() {
+            clock.advance(Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(client.join().unwrap().is_ok());
+        // How many pings went out depends on when the client armed its timer.
+        let pings: Vec<String> = server_rec.try_iter().collect();
+        assert!(!pings.is_empty());
+        assert!(pings.iter().all(|ping| ping == r#"{"type":"Ping"}"#));
+    }
+
+    #[test]
+    fn dead_server_is_reconnected() {
+        use crate::transport::memory::MemoryTransport;
+        use crate::transport::tcp::TcpTransport;
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (bot_end, bot) = MemoryTransport::pair();
+        bot.connect().unwrap();
+        let clock = Arc::new(clock::ManualClock::new());
+        let thread_clock = clock.clone();
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(default_client_config()));
+            let mut client: Client = Client::with_transports(handler, Arc::new(TcpTransport::new(addr)), Arc::new(bot_end),
+                &channel::ChannelConfig::default()).unwrap();
+            client.set_clock(thread_clock);
+            client.set_heartbeat(heartbeat_config(OnDeadPeer::Reconnect));
+            client.start()
+        });
+        let (dead, _) = listener.accept().unwrap();
+
+        let (accepted_snd, accepted) = crossbeam_channel::unbounded();
+        thread::spawn(move || accepted_snd.send(listener.accept().unwrap().0));
+        let second = advance_until(&clock, &accepted);
+
+        // The silent connection got pings and was then closed.
+        let pings: Vec<String> = BufReader::new(dead).lines().map(Result::unwrap).collect();
+        assert!(!pings.is_empty());
+        assert!(pings.iter().all(|ping| ping == r#"{"type":"Ping"}"#));
+
+        // The new connection carries the heartbeat.
+        let (lines_snd, lines) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            for line in BufReader::new(second).lines() {
+                if lines_snd.send(line.unwrap()).is_err() {
+                    return;
+                }
+            }
+        });
+        assert_eq!(advance_until(&clock, &lines), r#"{"type":"Ping"}"#);
+    }
+
+    /// Blocks in `read` until its sender is dropped.
+    struct ChannelReader(crossbeam_channel::Receiver<Vec<u8>>);
+
+    impl std::io::Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.recv() {
+                Ok(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                },
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    /// A silent server whose `close` doesn't end a pending read.
+    struct StuckTransport {
+        framed: transport::Framed,
+        streams: Mutex<Vec<crossbeam_channel::Sender<Vec<u8>>>>,
+        connects: crossbeam_channel::Sender<()>,
+    }
+
+    impl Transport for StuckTransport {
+        fn connect(&self) -> Result<(), transport::TransportError> {
+            let (stream, bytes) = crossbeam_channel::unbounded();
+            self.streams.lock().unwrap().push(stream);
+            self.framed.open(Box::new(std::io::BufReader::new(ChannelReader(bytes))), Box::new(std::io::sink()));
+            self.connects.send(()).unwrap();
+            Ok(())
+        }
+
+        fn send(&self, message: &str) -> Result<(), transport::TransportError> {
+            self.framed.send(message)
+        }
+
+        fn receive(&self) -> Result<Option<String>, transport::TransportError> {
+            self.framed.receive()
+        }
+
+        fn close(&self) -> Result<(), transport::TransportError> {
+            self.framed.close();
+            Ok(())
+        }
+
+        fn health(&self) -> transport::Health {
+            self.framed.health()
+        }
+    }
+
+    #[test]
+    fn reconnect_does_not_wait_for_a_pending_read() {
+        use crate::transport::memory::MemoryTransport;
+
+        let (connects_snd, connects) = crossbeam_channel::unbounded();
+        let server = StuckTransport{
+            framed: transport::Framed::new(transport::Framing::Newline),
+            streams: Mutex::new(Vec::new()),
+            connects: connects_snd,
+        };
+        let (bot_end, bot) = MemoryTransport::pair();
+        bot.connect().unwrap();
+        let clock = Arc::new(clock::ManualClock::new());
+        let thread_clock = clock.clone();
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(default_client_config()));
+            let mut client: Client = Client::with_transports(handler, Arc::new(server), Arc::new(bot_end),
+                &channel::ChannelConfig::default()).unwrap();
+            client.set_clock(thread_clock);
+            client.set_heartbeat(heartbeat_config(OnDeadPeer::Reconnect));
+            client.start()
+        });
+
+        connects.recv().unwrap();
+        for _ in 0..3 {
+            advance_until(&clock, &connects);
+        }
+    }
+
+    #[test]
+    fn pings_are_answered_on_the_side_they_came_from() {
+        let (server_snd, server_rec) = crossbeam_channel::unbounded();
+        let (bot_snd, bot_rec) = crossbeam_channel::unbounded();
+        let mut handler = handler::MessageHandler::new(default_client_config());
+        handler.add_output_channel(handler::Outputs::Server, server_snd);
+        handler.add_output_channel(handler::Outputs::Bot, bot_snd);
+        let mut client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), crossbeam_channel::never());
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        client.set_recorder(recorder.clone());
+
+        client.handle(Ok(r#"{"type":"Ping","seq":1}"#.to_string()), handler::Outputs::Bot).unwrap();
+        assert_eq!(bot_rec.try_recv().unwrap(), r#"{"type":"Pong","seq":1}"#);
+        assert!(server_rec.try_recv().is_err());
+
+        client.handle(Ok(r#"{"type":"Ping","seq":2}"#.to_string()), handler::Outputs::Server).unwrap();
+        assert_eq!(server_rec.try_recv().unwrap(), r#"{"type":"Pong","seq":2}"#);
+        assert!(bot_rec.try_recv().is_err());
+        assert_eq!(recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "Pong"), ("from", "Client")]), 2);
+    }
+
+    #[test]
+    fn answering_bot_stays_healthy() {
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        let clock = Arc::new(clock::ManualClock::new());
+        let (bot_inc_snd, bot_inc_rec) = crossbeam_channel::unbounded();
+        let (server_snd, server_rec) = crossbeam_channel::unbounded();
+        let (bot_snd, bot_rec) = crossbeam_channel::unbounded();
+        let (thread_clock, thread_recorder) = (clock.clone(), recorder.clone());
+        let client = thread::spawn(move || {
+            let mut handler = handler::MessageHandler::new(default_client_config());
+            handler.add_output_channel(handler::Outputs::Server, server_snd);
+            handler.add_output_channel(handler::Outputs::Bot, bot_snd);
+            let mut client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), bot_inc_rec);
+            client.set_clock(thread_clock);
+            client.set_recorder(thread_recorder);
+            client.set_bot_heartbeat(heartbeat_config(OnDeadPeer::Shutdown));
+            client.start()
+        });
+
+        for pongs in 1..=10 {
+            assert_eq!(advance_until(&clock, &bot_rec), r#"{"type":"Ping"}"#);
+            bot_inc_snd.send(r#"{"type":"Pong"}"#.to_string()).unwrap();
+            while recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "Pong"), ("from", "Bot")]) < pongs {
+                thread::yield_now();
+            }
+        }
+        assert!(!client.is_finished());
+        // The client counts a ping right after sending it.
+        while recorder.counter(metrics::MESSAGES_TOTAL, &[("type", "Ping"), ("from", "Client")]) < 10 {
+            thread::yield_now();
+        }
+        // Pongs of the bot stay in the client.
+        assert!(server_rec.try_recv().is_err());
+    }
+
+    #[test]
+    fn unresponsive_bot_is_flagged_and_times_out() {
+        let recorder = Arc::new(metrics::InMemoryRecorder::default());
+        let clock = Arc::new(clock::ManualClock::new());
+        let (_bot_inc_snd, bot_inc_rec) = crossbeam_channel::unbounded::<String>();
+        let (bot_snd, bot_rec) = crossbeam_channel::unbounded();
+        let (thread_clock, thread_recorder) = (clock.clone(), recorder.clone());
+        let client = thread::spawn(move || {
+            let mut handler = handler::MessageHandler::new(default_client_config());
+            handler.add_output_channel(handler::Outputs::Bot, bot_snd);
+            let mut client: Client = Client::new(Box::new(handler), crossbeam_channel::never(), bot_inc_rec);
+            client.set_clock(thread_clock);
+            client.set_recorder(thread_recorder);
+            client.set_bot_heartbeat(heartbeat_config(OnDeadPeer::Shutdown));
+            client.start()
+        });
+
+        while !client.is_finished() {
+            clock.advance(Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(client.join().unwrap().is_ok());
+        assert!(bot_rec.try_iter().count() >= 1);
+        assert_eq!(recorder.counter(metrics::UNRESPONSIVE_PEERS_TOTAL, &[("peer", "Bot")]), 1);
+        assert_eq!(recorder.counter(metrics::UNRESPONSIVE_PEERS_TOTAL, &[("peer", "Server")]), 0);
+    }
+
+    #[test]
+    fn hung_bot_process_is_restarted() {
+        use crate::transport::memory::MemoryTransport;
+        use crate::transport::subprocess::SubprocessTransport;
+
+        // Announces itself once, then reads without ever answering. Its
+        // output stays open on fd 3.
+        let bot = SubprocessTransport::new(vec![
+            "sh".to_string(), "-c".to_string(),
+            r#"echo '{"type":"Action","started":true}'; exec 3>&1 cat > /dev/null"#.to_string(),
+        ]);
+        let (server_end, server) = MemoryTransport::pair();
+        server.connect().unwrap();
+        let clock = Arc::new(clock::ManualClock::new());
+        let thread_clock = clock.clone();
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(default_client_config()));
+            let mut client: Client = Client::with_transports(handler, Arc::new(server_end), Arc::new(bot),
+                &channel::ChannelConfig::default()).unwrap();
+            client.set_clock(thread_clock);
+            client.set_bot_heartbeat(heartbeat_config(OnDeadPeer::Reconnect));
+            client.start()
+        });
+
+        let (actions_snd, actions) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            while let Ok(Some(action)) = server.receive() {
+                if actions_snd.send(action).is_err() {
+                    return;
+                }
+            }
+        });
+        let started = r#"{"type":"Action","started":true}"#;
+        assert_eq!(actions.recv().unwrap(), started);
+        assert_eq!(advance_until(&clock, &actions), started);
     }
 
     fn create_client_and_handle_message(
             msg_to_send: &str,
-            output_type: handler::Outputs,
-            expected_outbound_message: &str) {
+            output_type: handler::Outputs) -> String {
         let (svr_inc_snd, svr_inc_rec) = crossbeam_channel::bounded(1);
         let (bot_inc_snd, bot_inc_rec) = crossbeam_channel::bounded(1);
 
@@ -130,9 +1045,80 @@ mod client {
         match output_type {
             handler::Outputs::Server => svr_inc_snd.send(input_msg),
             handler::Outputs::Bot => bot_inc_snd.send(input_msg),
-        };
+            handler::Outputs::Viewer => panic!("a viewer has no input"),
+        }.unwrap();
+
+        output_rec.recv().unwrap()
+    }
+}
+
+/// Test cases shared by every client implementation, so the sync and async
+/// clients are held to the same routing semantics.
+#[cfg(test)]
+pub(crate) mod suite {
+    use crate::handler::Outputs;
+
+    pub(crate) trait Harness {
+        /// Start a client, send `msg_to_send` on the input that belongs to
+        /// `output_type` and return the message that arrives on `output_type`.
+        fn exchange(msg_to_send: &str, output_type: Outputs) -> String;
+    }
+
+    pub(crate) fn received_connected_message_should_respond_with_register_message<H: Harness>() {
+        let output_destination = Outputs::Server;
+        let incomming_message = r#"{"type": "Connected"}"#;
+        let expected_outbound_message = r#"{"type":"Register","clientType":"bot","game":"test_game","name":"test_bot"}"#;
+
+        assert_eq!(H::exchange(incomming_message, output_destination), expected_outbound_message)
+    }
+
+    pub(crate) fn receive_state_message_and_pass_on_to_bot<H: Harness>() {
+        let output_destination = Outputs::Bot;
+        let incomming_message = r#"{"type": "State", "other": "fields"}"#;
+        let expected_outbound_message = r#"{"type": "State", "other": "fields"}"#;
+
+        assert_eq!(H::exchange(incomming_message, output_destination), expected_outbound_message)
+    }
+
+    pub(crate) fn receive_error_message_and_pass_on_to_bot<H: Harness>() {
+        let output_destination = Outputs::Bot;
+        let incomming_message = r#"{"type": "Error", "message": "string"}"#;
+        let expected_outbound_message = r#"{"type": "Error", "message": "string"}"#;
+
+        assert_eq!(H::exchange(incomming_message, output_destination), expected_outbound_message)
+    }
 
-        let outgoing_message = output_rec.recv().unwrap();
-        assert_eq!(outgoing_message, expected_outbound_message.to_string())
+    pub(crate) fn receive_action_message_and_pass_on_to_server<H: Harness>() {
+        let output_destination = Outputs::Server;
+        let incomming_message = r#"{"type": "Action", "message": "string"}"#;
+        let expected_outbound_message = r#"{"type": "Action", "message": "string"}"#;
+
+        assert_eq!(H::exchange(incomming_message, output_destination), expected_outbound_message)
+    }
+
+    /// Generate a `#[test]` per shared case for the given `Harness`.
+    macro_rules! routing_tests {
+        ($harness:ty) => {
+            #[test]
+            fn received_connected_message_should_respond_with_register_message() {
+                suite::received_connected_message_should_respond_with_register_message::<$harness>()
+            }
+
+            #[test]
+            fn receive_state_message_and_pass_on_to_bot() {
+                suite::receive_state_message_and_pass_on_to_bot::<$harness>()
+            }
+
+            #[test]
+            fn receive_error_message_and_pass_on_to_bot() {
+                suite::receive_error_message_and_pass_on_to_bot::<$harness>()
+            }
+
+            #[test]
+            fn receive_action_message_and_pass_on_to_server() {
+                suite::receive_action_message_and_pass_on_to_server::<$harness>()
+            }
+        };
     }
+    pub(crate) use routing_tests;
 }
\ No newline at end of file
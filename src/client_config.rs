@@ -0,0 +1,226 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::secret::Secret;
+
+pub const MAX_NAME_LENGTH: usize = 32;
+pub const MAX_GAME_LENGTH: usize = 64;
+
+#[derive(Error,Debug,PartialEq)]
+pub enum ClientConfigError {
+    #[error("unknown client type `{0}`")]
+    UnknownClientType(String),
+
+    #[error("missing `{0}`")]
+    Missing(&'static str),
+
+    #[error("name should be between 1 and {max} characters but has {len}")]
+    NameLength{ len: usize, max: usize },
+
+    #[error("name contains invalid character `{0}`, only letters, digits, `_` and `-` are allowed")]
+    NameCharacter(char),
+
+    #[error("game should be between 1 and {max} characters without whitespace but is `{game}`")]
+    InvalidGame{ game: String, max: usize },
+}
+
+/// The role a client registers with.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Serialize,Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientType {
+    Bot,
+    Viewer,
+}
+
+impl ClientType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientType::Bot => "bot",
+            ClientType::Viewer => "viewer",
+        }
+    }
+}
+
+impl fmt::Display for ClientType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ClientType {
+    type Err = ClientConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bot" => Ok(ClientType::Bot),
+            "viewer" => Ok(ClientType::Viewer),
+            _ => Err(ClientConfigError::UnknownClientType(s.to_string())),
+        }
+    }
+}
+
+/// What a client registers with. Can only be created through the builder,
+/// so it is always valid.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ClientConfig {
+    client_type: ClientType,
+    game: String,
+    name: String,
+    token: Option<Secret>,
+}
+
+impl ClientConfig {
+    pub fn builder() -> ClientConfigBuilder {
+        ClientConfigBuilder::default()
+    }
+
+    pub fn client_type(&self) -> ClientType {
+        self.client_type
+    }
+
+    pub fn game(&self) -> &str {
+        &self.game
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Credential sent along with the registration, if any.
+    pub fn token(&self) -> Option<&Secret> {
+        self.token.as_ref()
+    }
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct ClientConfigBuilder {
+    client_type: Option<ClientType>,
+    game: Option<String>,
+    name: Option<String>,
+    token: Option<Secret>,
+}
+
+impl ClientConfigBuilder {
+    pub fn client_type(mut self, client_type: ClientType) -> Self {
+        self.client_type = Some(client_type);
+        self
+    }
+
+    pub fn game<S: Into<String>>(mut self, game: S) -> Self {
+        self.game = Some(game.into());
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn token(mut self, token: Secret) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Validate the configuration. The client type defaults to `Bot`.
+    pub fn build(self) -> Result<ClientConfig, ClientConfigError> {
+        let game = self.game.ok_or(ClientConfigError::Missing("game"))?;
+        let name = self.name.ok_or(ClientConfigError::Missing("name"))?;
+        validate_game(&game)?;
+        validate_name(&name)?;
+
+        Ok(ClientConfig{
+            client_type: self.client_type.unwrap_or(ClientType::Bot),
+            game,
+            name,
+            token: self.token,
+        })
+    }
+}
+
+fn validate_game(game: &str) -> Result<(), ClientConfigError> {
+    let len = game.chars().count();
+    if len == 0 || len > MAX_GAME_LENGTH || game.chars().any(char::is_whitespace) {
+        return Err(ClientConfigError::InvalidGame{
+            game: game.to_string(),
+            max: MAX_GAME_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), ClientConfigError> {
+    let len = name.chars().count();
+    if len == 0 || len > MAX_NAME_LENGTH {
+        return Err(ClientConfigError::NameLength{ len, max: MAX_NAME_LENGTH });
+    }
+    match name.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-')) {
+        Some(c) => Err(ClientConfigError::NameCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> ClientConfigBuilder {
+        ClientConfig::builder()
+            .game("test_game")
+            .name("test_bot")
+    }
+
+    #[test]
+    fn build_valid_config_defaults_to_bot() {
+        let config = builder().build().unwrap();
+        assert_eq!(config.client_type(), ClientType::Bot);
+        assert_eq!(config.game(), "test_game");
+        assert_eq!(config.name(), "test_bot");
+    }
+
+    #[test]
+    fn build_viewer_config() {
+        let config = builder().client_type(ClientType::Viewer).build().unwrap();
+        assert_eq!(config.client_type().as_str(), "viewer");
+    }
+
+    #[test]
+    fn token_is_redacted_in_debug() {
+        let config = builder().token(Secret::new("hunter2")).build().unwrap();
+        assert_eq!(config.token().unwrap().expose(), "hunter2");
+        assert!(!format!("{:?}", config).contains("hunter2"));
+    }
+
+    #[test]
+    fn client_type_from_str() {
+        assert_eq!("bot".parse::<ClientType>(), Ok(ClientType::Bot));
+        assert_eq!("viewer".parse::<ClientType>(), Ok(ClientType::Viewer));
+        assert_eq!("Bot".parse::<ClientType>(), Err(ClientConfigError::UnknownClientType("Bot".to_string())));
+    }
+
+    #[test]
+    fn missing_fields_are_rejected() {
+        assert_eq!(ClientConfig::builder().name("n").build(), Err(ClientConfigError::Missing("game")));
+        assert_eq!(ClientConfig::builder().game("g").build(), Err(ClientConfigError::Missing("name")));
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        assert_eq!(builder().name("").build(), Err(ClientConfigError::NameLength{ len: 0, max: MAX_NAME_LENGTH }));
+        assert_eq!(builder().name("a".repeat(33)).build(), Err(ClientConfigError::NameLength{ len: 33, max: MAX_NAME_LENGTH }));
+        assert_eq!(builder().name("my bot").build(), Err(ClientConfigError::NameCharacter(' ')));
+        assert_eq!(builder().name("bot!").build(), Err(ClientConfigError::NameCharacter('!')));
+    }
+
+    #[test]
+    fn invalid_games_are_rejected() {
+        for game in &["", " ", "planet wars", "game\t"] {
+            match builder().game(*game).build() {
+                Err(ClientConfigError::InvalidGame{ .. }) => (),
+                result => panic!("Expected an invalid game error for `{}` but got {:?}", game, result),
+            }
+        }
+    }
+}
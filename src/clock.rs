@@ -0,0 +1,175 @@
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{select, Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+/// Source of time for timeouts and measurements, so they can be tested
+/// without waiting.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// A channel that receives the time once `duration` has passed, to use
+    /// in a `select!`.
+    fn after(&self, duration: Duration) -> Receiver<Instant>;
+}
+
+/// A deadline on a `Clock`, the replacement for `Receiver::recv_deadline`.
+pub struct Deadline {
+    timer: Receiver<Instant>,
+    passed: Cell<bool>,
+}
+
+impl Deadline {
+    pub fn after(clock: &dyn Clock, duration: Duration) -> Self {
+        Deadline{ timer: clock.after(duration), passed: Cell::new(false) }
+    }
+
+    /// Wait for a message until the deadline has passed. After that only
+    /// messages that are already there are returned.
+    pub fn recv<T>(&self, receiver: &Receiver<T>) -> Result<T, RecvTimeoutError> {
+        if !self.passed.get() {
+            select!{
+                recv(receiver) -> message => return message.map_err(|_| RecvTimeoutError::Disconnected),
+                recv(self.timer) -> _ => self.passed.set(true),
+            }
+        }
+        receiver.try_recv().map_err(|e| match e {
+            TryRecvError::Empty => RecvTimeoutError::Timeout,
+            TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
+
+/// The wall clock.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn after(&self, duration: Duration) -> Receiver<Instant> {
+        crossbeam_channel::after(duration)
+    }
+}
+
+/// A clock that only moves when told to. Timers fire during `advance`.
+pub struct ManualClock {
+    inner: Mutex<Manual>,
+}
+
+struct Manual {
+    now: Instant,
+    timers: Vec<(Instant, Sender<Instant>)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock{
+            inner: Mutex::new(Manual{ now: Instant::now(), timers: Vec::new() }),
+        }
+    }
+
+    /// Move the clock forward and fire every timer that is due.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now += duration;
+        let now = inner.now;
+        inner.timers.retain(|(deadline, timer)| {
+            if *deadline > now {
+                return true;
+            }
+            let _ = timer.try_send(now);
+            false
+        });
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn after(&self, duration: Duration) -> Receiver<Instant> {
+        let (timer, receiver) = crossbeam_channel::bounded(1);
+        let mut inner = self.inner.lock().unwrap();
+        let deadline = inner.now + duration;
+        if duration == Duration::from_secs(0) {
+            let _ = timer.try_send(deadline);
+        } else {
+            inner.timers.push((deadline, timer));
+        }
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_on_advance() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(clock.now() - start, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn timers_fire_when_due() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let short = clock.after(Duration::from_millis(100));
+        let long = clock.after(Duration::from_secs(10));
+
+        clock.advance(Duration::from_millis(99));
+        assert!(short.try_recv().is_err());
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(short.try_recv().unwrap() - start, Duration::from_millis(100));
+        assert!(long.try_recv().is_err());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(long.try_recv().is_ok());
+    }
+
+    #[test]
+    fn zero_timer_fires_at_once() {
+        let clock = ManualClock::new();
+        assert!(clock.after(Duration::from_secs(0)).try_recv().is_ok());
+    }
+
+    #[test]
+    fn deadline_stays_passed() {
+        let clock = ManualClock::new();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let deadline = Deadline::after(&clock, Duration::from_secs(1));
+
+        sender.send(1).unwrap();
+        assert_eq!(deadline.recv(&receiver), Ok(1));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(deadline.recv(&receiver), Err(RecvTimeoutError::Timeout));
+        sender.send(2).unwrap();
+        assert_eq!(deadline.recv(&receiver), Ok(2));
+
+        drop(sender);
+        assert_eq!(deadline.recv(&receiver), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn system_clock_timer() {
+        let timer = SystemClock.after(Duration::from_millis(1));
+        assert!(timer.recv().is_ok());
+    }
+}
@@ -0,0 +1,1003 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::channel;
+use crate::client_config::{ClientConfig, ClientConfigError, ClientType};
+use crate::heartbeat::{HeartbeatConfig, OnDeadPeer};
+use crate::rate_limit::{Limit, LimitPolicy, RateLimit};
+use crate::referee::RefereeConfig;
+use crate::secret::Secret;
+use crate::transport::Framing;
+use crate::transport::tcp::TcpTransport;
+
+/// Prefix of the environment variables that override settings, for example
+/// `WARTEMIS_CLIENT_NAME` sets `client.name`.
+pub const ENV_PREFIX: &str = "WARTEMIS_";
+/// Environment variable holding the path of the config file.
+pub const ENV_CONFIG_FILE: &str = "WARTEMIS_CONFIG";
+
+#[derive(Error,Debug)]
+pub enum ConfigError {
+    #[error("read config file `{path}`: {source}")]
+    Read{
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("parse config file `{path}`: {reason}")]
+    Parse{
+        path: PathBuf,
+        reason: String,
+    },
+
+    #[error("unknown config key `{key}`")]
+    UnknownKey{ key: String },
+
+    #[error("invalid value for `{key}`: {reason}")]
+    Invalid{
+        key: String,
+        reason: String,
+    },
+
+    #[error("missing value for command line flag `{flag}`")]
+    MissingValue{ flag: String },
+}
+
+/// Every setting of the client. Values are layered: defaults, then the config
+/// file, then environment variables, then command line flags.
+#[derive(Debug,Clone,Default,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub client: ClientSettings,
+    pub server: ServerSettings,
+    pub tls: TlsSettings,
+    pub bot: BotSettings,
+    pub timeouts: TimeoutSettings,
+    pub routing: RoutingSettings,
+    pub rate_limit: RateLimitSettings,
+    pub heartbeat: HeartbeatSettings,
+    pub bot_heartbeat: HeartbeatSettings,
+    pub logging: LoggingSettings,
+    /// Value of the `client.token_env` variable in the environment the
+    /// settings were loaded from.
+    #[serde(skip)]
+    env_token: Option<Secret>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSettings {
+    pub client_type: String,
+    pub game: String,
+    pub name: String,
+    /// File to read the registration token from, empty for none.
+    pub token_file: String,
+    /// Environment variable to read the registration token from, empty for none.
+    pub token_env: String,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub url: String,
+}
+
+/// Used when the server url starts with `wss://`, see
+/// `Settings::server_transport`.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    pub system_roots: bool,
+    /// PEM bundle with extra trusted certificate authorities, empty for none.
+    pub ca_file: String,
+    /// PEM certificate chain to authenticate with, empty for none.
+    pub client_cert: String,
+    pub client_key: String,
+    /// Skip verifying the server certificate, for local testing only.
+    pub insecure: bool,
+}
+
+#[derive(Debug,Clone,Default,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotSettings {
+    /// Program and arguments to start the bot with.
+    pub command: Vec<String>,
+    /// How messages are delimited on the bot's standard input and output.
+    pub framing: Framing,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSettings {
+    /// How long connecting to the server may take.
+    pub connect_ms: u64,
+    /// How long players get to answer a `State` in a local match.
+    pub action_ms: u64,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingSettings {
+    /// 0 makes a rendezvous channel, see `ChannelConfig`.
+    pub capacity: usize,
+    pub overflow: channel::OverflowPolicy,
+}
+
+/// Limits on messages sent to the server.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    pub policy: LimitPolicy,
+    pub max_queue: usize,
+    pub limits: Vec<LimitSettings>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitSettings {
+    /// Message type the limit applies to, like `Action`.
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Pings to the server, or the bot, to notice when it stopped responding.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatSettings {
+    /// Time between pings, 0 turns heartbeats off.
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    pub on_dead_peer: OnDeadPeer,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    pub level: String,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings{
+            client_type: "bot".to_string(),
+            game: String::new(),
+            name: String::new(),
+            token_file: String::new(),
+            token_env: String::new(),
+        }
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings{
+            url: "ws://localhost:8080".to_string(),
+        }
+    }
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        TlsSettings{
+            system_roots: true,
+            ca_file: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
+            insecure: false,
+        }
+    }
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        TimeoutSettings{
+            connect_ms: 5000,
+            action_ms: 1000,
+        }
+    }
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        let channel_config = channel::ChannelConfig::default();
+        RoutingSettings{
+            capacity: channel_config.capacity,
+            overflow: channel_config.policy,
+        }
+    }
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        RateLimitSettings{
+            policy: LimitPolicy::Queue,
+            max_queue: 16,
+            limits: Vec::new(),
+        }
+    }
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        HeartbeatSettings{
+            interval_ms: 0,
+            timeout_ms: 15000,
+            on_dead_peer: OnDeadPeer::Reconnect,
+        }
+    }
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        LoggingSettings{
+            level: "info".to_string(),
+        }
+    }
+}
+
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+const URL_SCHEMES: [&str; 3] = ["ws://", "wss://", "tcp://"];
+
+impl Settings {
+    /// Load the settings from the process environment and command line.
+    pub fn from_environment() -> Result<Self, ConfigError> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Settings::load(std::env::vars(), &args)
+    }
+
+    /// Layer the config file, environment variables and command line flags
+    /// over the defaults. The config file is taken from `--config <path>` or
+    /// `WARTEMIS_CONFIG`, other flags look like `--client.name <value>`.
+    pub fn load<I>(env: I, args: &[String]) -> Result<Self, ConfigError>
+            where I: IntoIterator<Item = (String, String)> {
+        let defaults = serde_json::to_value(Settings::default())
+            .expect("default settings always serialize");
+
+        let env: Vec<(String, String)> = env.into_iter().collect();
+        let mut config_file = None;
+        let mut env_overrides = Vec::new();
+        for (name, value) in env.iter().cloned() {
+            if name == ENV_CONFIG_FILE {
+                config_file = Some(PathBuf::from(value));
+            } else if let Some(key) = env_key(&defaults, &name) {
+                env_overrides.push((key, value));
+            }
+        }
+
+        let (cli_config_file, cli_overrides) = parse_args(args)?;
+        let config_file = cli_config_file.or(config_file);
+
+        let mut layered = defaults.clone();
+        if let Some(path) = config_file {
+            let file = read_file(&path)?;
+            check_known_keys(&defaults, &file, "")?;
+            merge(&mut layered, file);
+        }
+        for (key, value) in env_overrides.into_iter().chain(cli_overrides) {
+            set_key(&defaults, &mut layered, &key, &value)?;
+        }
+
+        let mut settings: Settings = serde_path_to_error::deserialize(layered)
+            .map_err(|e| ConfigError::Invalid{
+                key: e.path().to_string(),
+                reason: e.into_inner().to_string(),
+            })?;
+        settings.env_token = env.into_iter()
+            .find(|(name, _)| *name == settings.client.token_env)
+            .map(|(_, value)| Secret::new(value));
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, reason: &str| Err(ConfigError::Invalid{
+            key: key.to_string(),
+            reason: reason.to_string(),
+        });
+
+        self.client_config()?;
+        if !URL_SCHEMES.iter().any(|scheme| self.server.url.starts_with(scheme)) {
+            return invalid("server.url", "should start with ws://, wss:// or tcp://");
+        }
+        if self.tls.client_cert.is_empty() != self.tls.client_key.is_empty() {
+            return invalid("tls.client_key", "client_cert and client_key should be set together");
+        }
+        if !self.rate_limit.limits.iter().all(|limit| limit.limit().is_valid()) {
+            return invalid("rate_limit.limits", "per_second and burst should be above 0");
+        }
+        if !self.heartbeat.is_valid() {
+            return invalid("heartbeat.timeout_ms", "should be longer than heartbeat.interval_ms");
+        }
+        if !self.bot_heartbeat.is_valid() {
+            return invalid("bot_heartbeat.timeout_ms", "should be longer than bot_heartbeat.interval_ms");
+        }
+        if !LOG_LEVELS.contains(&self.logging.level.as_str()) {
+            return invalid("logging.level", "should be one of trace, debug, info, warn or error");
+        }
+        Ok(())
+    }
+
+    pub fn client_config(&self) -> Result<ClientConfig, ConfigError> {
+        let mut builder = ClientConfig::builder()
+            .client_type(self.client.client_type.parse::<ClientType>().map_err(invalid_client)?)
+            .game(self.client.game.clone())
+            .name(self.client.name.clone());
+        if let Some(token) = self.token()? {
+            builder = builder.token(token);
+        }
+        builder.build().map_err(invalid_client)
+    }
+
+    fn token(&self) -> Result<Option<Secret>, ConfigError> {
+        let invalid = |key: &str, reason: String| ConfigError::Invalid{ key: key.to_string(), reason };
+
+        if !self.client.token_file.is_empty() {
+            return Secret::from_file(&self.client.token_file)
+                .map(Some)
+                .map_err(|e| invalid("client.token_file", e.to_string()));
+        }
+        if !self.client.token_env.is_empty() {
+            let value = self.env_token.as_ref().map(Secret::expose);
+            return Secret::from_env_value(&self.client.token_env, value)
+                .map(Some)
+                .map_err(|e| invalid("client.token_env", e.to_string()));
+        }
+        Ok(None)
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_config(&self) -> crate::tls::TlsConfig {
+        let path = |path: &str| Some(path).filter(|p| !p.is_empty()).map(PathBuf::from);
+
+        crate::tls::TlsConfig{
+            system_roots: self.tls.system_roots,
+            ca_file: path(&self.tls.ca_file),
+            client_certificate: path(&self.tls.client_cert).zip(path(&self.tls.client_key))
+                .map(|(cert_file, key_file)| crate::tls::ClientCertificate{ cert_file, key_file }),
+            insecure: self.tls.insecure,
+        }
+    }
+
+    /// Transport to `server.url`. There is no WebSocket transport, every
+    /// scheme speaks newline delimited JSON over TCP and `wss://` adds TLS.
+    pub fn server_transport(&self) -> Result<TcpTransport, ConfigError> {
+        let (scheme, rest) = self.server.url.split_once("://")
+            .ok_or_else(|| ConfigError::Invalid{ key: "server.url".to_string(), reason: "missing scheme".to_string() })?;
+        let addr = rest.split('/').next().unwrap_or(rest);
+        let transport = TcpTransport::new(addr).connect_timeout(Duration::from_millis(self.timeouts.connect_ms));
+        if scheme != "wss" {
+            return Ok(transport);
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let config = self.tls_config().client_config()
+                .map_err(|e| ConfigError::Invalid{ key: "tls".to_string(), reason: e.to_string() })?;
+            Ok(transport.with_tls(host, config))
+        }
+        #[cfg(not(feature = "tls"))]
+        Err(ConfigError::Invalid{ key: "server.url".to_string(), reason: "wss:// needs the tls feature".to_string() })
+    }
+
+    /// Referee for a local match of `client.game`, players get
+    /// `timeouts.action_ms` to answer a `State`.
+    pub fn referee_config(&self) -> RefereeConfig {
+        RefereeConfig{
+            game: self.client.game.clone(),
+            action_timeout: Duration::from_millis(self.timeouts.action_ms),
+            ..RefereeConfig::default()
+        }
+    }
+
+    /// Middleware layer for the configured limits, `None` without limits.
+    pub fn rate_limit(&self) -> Result<Option<RateLimit>, ConfigError> {
+        if self.rate_limit.limits.is_empty() {
+            return Ok(None);
+        }
+        let rate_limit = RateLimit::new(self.rate_limit.policy).max_queue(self.rate_limit.max_queue);
+        self.rate_limit.limits.iter()
+            .try_fold(rate_limit, |rate_limit, limit| rate_limit.limit(&limit.message_type, limit.limit()))
+            .map(Some)
+            .map_err(|e| ConfigError::Invalid{ key: "rate_limit.limits".to_string(), reason: e.to_string() })
+    }
+
+    /// Heartbeat for the server link, `None` when turned off.
+    pub fn heartbeat(&self) -> Option<HeartbeatConfig> {
+        self.heartbeat.config()
+    }
+
+    /// Health check of the bot, `None` when turned off.
+    pub fn bot_heartbeat(&self) -> Option<HeartbeatConfig> {
+        self.bot_heartbeat.config()
+    }
+
+    pub fn channel_config(&self) -> channel::ChannelConfig {
+        channel::ChannelConfig{
+            capacity: self.routing.capacity,
+            policy: self.routing.overflow,
+        }
+    }
+}
+
+impl LimitSettings {
+    fn limit(&self) -> Limit {
+        Limit{ per_second: self.per_second, burst: self.burst }
+    }
+}
+
+impl HeartbeatSettings {
+    fn is_valid(&self) -> bool {
+        self.interval_ms == 0 || self.timeout_ms > self.interval_ms
+    }
+
+    fn config(&self) -> Option<HeartbeatConfig> {
+        if self.interval_ms == 0 {
+            return None;
+        }
+        Some(HeartbeatConfig{
+            interval: Duration::from_millis(self.interval_ms),
+            timeout: Duration::from_millis(self.timeout_ms),
+            on_dead_peer: self.on_dead_peer,
+        })
+    }
+}
+
+fn invalid_client(e: ClientConfigError) -> ConfigError {
+    let key = match e {
+        ClientConfigError::UnknownClientType(_) => "client.client_type",
+        ClientConfigError::Missing(_) => "client",
+        ClientConfigError::NameLength{..} | ClientConfigError::NameCharacter(_) => "client.name",
+        ClientConfigError::InvalidGame{..} => "client.game",
+    };
+    ConfigError::Invalid{
+        key: key.to_string(),
+        reason: e.to_string(),
+    }
+}
+
+/// Setting an environment variable overrides, `None` for variables that
+/// don't name one. Section names can contain underscores themselves, so the
+/// longest matching section wins: `WARTEMIS_RATE_LIMIT_POLICY` sets
+/// `rate_limit.policy`.
+fn env_key(defaults: &Value, name: &str) -> Option<String> {
+    let rest = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+    let mut sections: Vec<&String> = defaults.as_object()?.keys().collect();
+    sections.sort_by_key(|section| std::cmp::Reverse(section.len()));
+
+    sections.into_iter().find_map(|section| {
+        let key = rest.strip_prefix(section.as_str())?.strip_prefix('_')?;
+        defaults[section].get(key)?;
+        Some(format!("{}.{}", section, key))
+    })
+}
+
+type Overrides = Vec<(String, String)>;
+
+/// Split the command line in the config file and `key = value` overrides.
+fn parse_args(args: &[String]) -> Result<(Option<PathBuf>, Overrides), ConfigError> {
+    let mut config_file = None;
+    let mut overrides = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(ConfigError::UnknownKey{ key: arg.clone() }),
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                let value = args.next()
+                    .ok_or_else(|| ConfigError::MissingValue{ flag: arg.clone() })?;
+                (flag.to_string(), value.clone())
+            },
+        };
+
+        if key == "config" {
+            config_file = Some(PathBuf::from(value));
+        } else {
+            overrides.push((key, value));
+        }
+    }
+    Ok((config_file, overrides))
+}
+
+fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Read{ path: path.to_path_buf(), source: e })?;
+    let parse_error = |reason: String| ConfigError::Parse{ path: path.to_path_buf(), reason };
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&content)
+            .map_err(|e| parse_error(e.to_string())),
+        _ => toml::from_str(&content)
+            .map_err(|e| parse_error(e.to_string())),
+    }
+}
+
+fn check_known_keys(known: &Value, value: &Value, prefix: &str) -> Result<(), ConfigError> {
+    if let (Value::Object(known), Value::Object(map)) = (known, value) {
+        for (key, value) in map {
+            let full_key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            match known.get(key) {
+                Some(known) => check_known_keys(known, value, &full_key)?,
+                None => return Err(ConfigError::UnknownKey{ key: full_key }),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn merge(target: &mut Value, layer: Value) {
+    match (target, layer) {
+        (Value::Object(target), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(target.entry(key).or_insert(Value::Null), value);
+            }
+        },
+        (target, layer) => *target = layer,
+    }
+}
+
+/// Set a dotted `key` from a string, converting it to the type of the default.
+fn set_key(defaults: &Value, target: &mut Value, key: &str, raw: &str) -> Result<(), ConfigError> {
+    let unknown = || ConfigError::UnknownKey{ key: key.to_string() };
+    let invalid = |reason: &str| ConfigError::Invalid{ key: key.to_string(), reason: reason.to_string() };
+
+    let default = key.split('.')
+        .try_fold(defaults, |value, part| value.get(part))
+        .ok_or_else(unknown)?;
+
+    let value = match default {
+        Value::String(_) => Value::String(raw.to_string()),
+        Value::Bool(_) => raw.parse::<bool>()
+            .map(Value::Bool)
+            .map_err(|_| invalid("expected true or false"))?,
+        Value::Number(_) => serde_json::from_str::<serde_json::Number>(raw)
+            .map(Value::Number)
+            .map_err(|_| invalid("expected a number"))?,
+        Value::Array(_) => Value::Array(raw.split_whitespace()
+            .map(|part| Value::String(part.to_string()))
+            .collect()),
+        _ => return Err(unknown()),
+    };
+
+    let mut layer = value;
+    for part in key.split('.').rev() {
+        let mut map = Map::new();
+        map.insert(part.to_string(), layer);
+        layer = Value::Object(map);
+    }
+    merge(target, layer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    /// A file in the temp directory, removed on drop.
+    struct TempFile(PathBuf);
+
+    impl std::ops::Deref for TempFile {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_config(name: &str, content: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("wartemis-config-{}-{}", std::process::id(), name));
+        fs::File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        TempFile(path)
+    }
+
+    fn required() -> Vec<(String, String)> {
+        env(&[("WARTEMIS_CLIENT_GAME", "game"), ("WARTEMIS_CLIENT_NAME", "name")])
+    }
+
+    fn expect_invalid(result: Result<Settings, ConfigError>, expected_key: &str) {
+        match result {
+            Err(ConfigError::Invalid{ ref key, .. }) if key == expected_key => (),
+            _ => panic!("Expected an invalid `{}` but got {:?}", expected_key, result),
+        }
+    }
+
+    #[cfg(test)]
+    mod layering {
+        use super::*;
+
+        #[test]
+        fn defaults_are_used_without_overrides() {
+            let settings = Settings::load(required(), &[]).unwrap();
+            assert_eq!(settings.client.client_type, "bot");
+            assert_eq!(settings.timeouts, TimeoutSettings::default());
+        }
+
+        #[test]
+        fn toml_file_overrides_defaults() {
+            let path = write_config("layer.toml", r#"
+                [client]
+                game = "planets"
+                name = "from_file"
+
+                [routing]
+                capacity = 8
+                overflow = "drop_oldest"
+            "#);
+
+            let settings = Settings::load(vec![], &args(&["--config", path.to_str().unwrap()])).unwrap();
+            assert_eq!(settings.client.name, "from_file");
+            assert_eq!(settings.channel_config().capacity, 8);
+            assert_eq!(settings.channel_config().policy, channel::OverflowPolicy::DropOldest);
+        }
+
+        #[test]
+        fn timeouts_reach_the_referee() {
+            let settings = Settings::load(required(), &args(&["--timeouts.action_ms", "250"])).unwrap();
+            let config = settings.referee_config();
+            assert_eq!(config.game, "game");
+            assert_eq!(config.action_timeout, Duration::from_millis(250));
+        }
+
+        #[test]
+        fn zero_capacity_is_a_rendezvous_channel() {
+            let settings = Settings::load(required(), &args(&["--routing.capacity", "0"])).unwrap();
+            assert_eq!(settings.channel_config().capacity, 0);
+        }
+
+        #[test]
+        fn bot_framing_is_selectable() {
+            assert_eq!(Settings::load(required(), &[]).unwrap().bot.framing, Framing::Newline);
+
+            let settings = Settings::load(required(), &args(&["--bot.framing", "length_prefixed"])).unwrap();
+            assert_eq!(settings.bot.framing, Framing::LengthPrefixed);
+        }
+
+        #[test]
+        fn json_file_is_supported() {
+            let path = write_config("layer.json", r#"{"client": {"game": "planets", "name": "json"}}"#);
+
+            let settings = Settings::load(env(&[("WARTEMIS_CONFIG", path.to_str().unwrap())]), &[]).unwrap();
+            assert_eq!(settings.client_config().unwrap().name(), "json");
+        }
+
+        #[test]
+        fn env_overrides_file_and_cli_overrides_env() {
+            let path = write_config("order.toml", r#"
+                [client]
+                game = "planets"
+                name = "from_file"
+                [timeouts]
+                action_ms = 10
+            "#);
+            let vars = env(&[
+                ("WARTEMIS_CONFIG", path.to_str().unwrap()),
+                ("WARTEMIS_CLIENT_NAME", "from_env"),
+                ("WARTEMIS_TIMEOUTS_ACTION_MS", "20"),
+                ("WARTEMIS_BOT_COMMAND", "python bot.py"),
+            ]);
+
+            let settings = Settings::load(vars, &args(&["--client.name=from_cli"])).unwrap();
+            assert_eq!(settings.client.name, "from_cli");
+            assert_eq!(settings.client.game, "planets");
+            assert_eq!(settings.timeouts.action_ms, 20);
+            assert_eq!(settings.bot.command, vec!["python", "bot.py"]);
+        }
+
+        #[test]
+        fn env_sets_sections_with_an_underscore() {
+            let mut vars = required();
+            vars.extend(env(&[
+                ("WARTEMIS_RATE_LIMIT_POLICY", "drop"),
+                ("WARTEMIS_BOT_HEARTBEAT_INTERVAL_MS", "500"),
+                ("WARTEMIS_BOT_FRAMING", "json"),
+            ]));
+
+            let settings = Settings::load(vars, &[]).unwrap();
+            assert_eq!(settings.rate_limit.policy, LimitPolicy::Drop);
+            assert_eq!(settings.bot_heartbeat.interval_ms, 500);
+            assert_eq!(settings.bot.framing, Framing::Json);
+        }
+
+        #[test]
+        fn unrelated_env_vars_are_ignored() {
+            let mut vars = required();
+            vars.extend(env(&[("WARTEMIS_BOT_TOKEN", "abc"), ("WARTEMIS_DEBUG", "1"), ("WARTEMIS_", "")]));
+
+            let settings = Settings::load(vars, &[]).unwrap();
+            assert_eq!(settings.bot, BotSettings::default());
+        }
+    }
+
+    #[cfg(test)]
+    mod rate_limit {
+        use super::*;
+
+        #[test]
+        fn limits_are_read_per_type() {
+            assert!(Settings::load(required(), &[]).unwrap().rate_limit().unwrap().is_none());
+
+            let path = write_config("rate_limit.toml", r#"
+                [client]
+                game = "planets"
+                name = "limited"
+
+                [rate_limit]
+                policy = "error"
+
+                [[rate_limit.limits]]
+                type = "Action"
+                per_second = 5
+                burst = 2
+            "#);
+            let settings = Settings::load(vec![], &args(&["--config", path.to_str().unwrap()])).unwrap();
+            assert_eq!(settings.rate_limit.policy, LimitPolicy::Error);
+            assert_eq!(settings.rate_limit.limits, vec![LimitSettings{ message_type: "Action".to_string(), per_second: 5.0, burst: 2 }]);
+            assert!(settings.rate_limit().unwrap().is_some());
+        }
+
+        #[test]
+        fn invalid_limits_point_to_key() {
+            let path = write_config("zero_burst.toml", r#"
+                [client]
+                game = "planets"
+                name = "limited"
+
+                [[rate_limit.limits]]
+                type = "Action"
+                per_second = 5
+                burst = 0
+            "#);
+            expect_invalid(Settings::load(vec![], &args(&["--config", path.to_str().unwrap()])), "rate_limit.limits");
+            expect_invalid(Settings::load(required(), &args(&["--rate_limit.policy", "ignore"])), "rate_limit.policy");
+        }
+    }
+
+    #[cfg(test)]
+    mod heartbeat {
+        use super::*;
+
+        #[test]
+        fn heartbeat_is_off_by_default() {
+            assert!(Settings::load(required(), &[]).unwrap().heartbeat().is_none());
+
+            let settings = Settings::load(required(), &args(&[
+                "--heartbeat.interval_ms", "2000",
+                "--heartbeat.on_dead_peer", "shutdown",
+            ])).unwrap();
+            assert_eq!(settings.heartbeat(), Some(HeartbeatConfig{
+                interval: Duration::from_secs(2),
+                timeout: Duration::from_secs(15),
+                on_dead_peer: OnDeadPeer::Shutdown,
+            }));
+        }
+
+        #[test]
+        fn timeout_should_exceed_interval() {
+            let args = args(&["--heartbeat.interval_ms", "5000", "--heartbeat.timeout_ms", "5000"]);
+            expect_invalid(Settings::load(required(), &args), "heartbeat.timeout_ms");
+        }
+
+        #[test]
+        fn bot_heartbeat_is_separate() {
+            let path = write_config("bot_heartbeat.toml", r#"
+                [client]
+                game = "planets"
+                name = "checked"
+
+                [bot_heartbeat]
+                interval_ms = 500
+                timeout_ms = 2000
+            "#);
+            let settings = Settings::load(vec![], &args(&["--config", path.to_str().unwrap()])).unwrap();
+            assert!(settings.heartbeat().is_none());
+            assert_eq!(settings.bot_heartbeat(), Some(HeartbeatConfig{
+                interval: Duration::from_millis(500),
+                timeout: Duration::from_secs(2),
+                on_dead_peer: OnDeadPeer::Reconnect,
+            }));
+
+            let args = args(&["--bot_heartbeat.interval_ms", "500", "--bot_heartbeat.timeout_ms", "100"]);
+            expect_invalid(Settings::load(required(), &args), "bot_heartbeat.timeout_ms");
+        }
+    }
+
+    #[cfg(test)]
+    mod tls {
+        use super::*;
+
+        #[test]
+        fn insecure_is_opt_in() {
+            let settings = Settings::load(required(), &[]).unwrap();
+            assert!(!settings.tls.insecure);
+
+            let settings = Settings::load(required(), &args(&["--tls.insecure", "true"])).unwrap();
+            assert!(settings.tls.insecure);
+        }
+
+        #[test]
+        fn client_cert_requires_key() {
+            expect_invalid(Settings::load(required(), &args(&["--tls.client_cert", "client.pem"])), "tls.client_key");
+        }
+
+        #[test]
+        fn plain_urls_connect_without_tls() {
+            use crate::transport::Transport;
+            use std::io::Read;
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("tcp://{}/game", listener.local_addr().unwrap());
+            let settings = Settings::load(required(), &args(&["--server.url", &url])).unwrap();
+
+            let transport = settings.server_transport().unwrap();
+            transport.connect().unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            transport.send("{}").unwrap();
+            let mut line = [0; 3];
+            server.read_exact(&mut line).unwrap();
+            assert_eq!(&line, b"{}\n");
+        }
+
+        #[cfg(feature = "tls")]
+        #[test]
+        fn wss_url_uses_the_tls_settings() {
+            let settings = Settings::load(required(), &args(&[
+                "--server.url", "wss://localhost:8443",
+                "--tls.system_roots", "false",
+            ])).unwrap();
+            match settings.server_transport() {
+                Err(ConfigError::Invalid{ ref key, ref reason }) if key == "tls" => assert!(reason.contains("no trusted root")),
+                other => panic!("Expected an invalid tls config but got {:?}", other.map(|_| ())),
+            }
+        }
+
+        #[cfg(not(feature = "tls"))]
+        #[test]
+        fn wss_url_needs_the_tls_feature() {
+            let settings = Settings::load(required(), &args(&["--server.url", "wss://localhost:8443"])).unwrap();
+            assert!(matches!(settings.server_transport(), Err(ConfigError::Invalid{ .. })));
+        }
+
+        #[cfg(feature = "tls")]
+        #[test]
+        fn tls_config_from_settings() {
+            let settings = Settings::load(required(), &args(&[
+                "--tls.ca_file", "ca.pem",
+                "--tls.client_cert", "client.pem",
+                "--tls.client_key", "client.key",
+            ])).unwrap();
+
+            let config = settings.tls_config();
+            assert!(config.system_roots);
+            assert_eq!(config.ca_file, Some(PathBuf::from("ca.pem")));
+            assert_eq!(config.client_certificate.unwrap().key_file, PathBuf::from("client.key"));
+        }
+    }
+
+    #[cfg(test)]
+    mod token {
+        use super::*;
+
+        #[test]
+        fn token_is_read_from_file() {
+            let token_path = write_config("token", "hunter2\n");
+            let mut vars = required();
+            vars.extend(env(&[("WARTEMIS_CLIENT_TOKEN_FILE", token_path.to_str().unwrap())]));
+
+            let settings = Settings::load(vars, &[]).unwrap();
+            let config = settings.client_config().unwrap();
+            assert_eq!(config.token().unwrap().expose(), "hunter2");
+        }
+
+        #[test]
+        fn token_is_read_from_env_var() {
+            let mut vars = required();
+            vars.extend(env(&[("GAME_TOKEN", "hunter2")]));
+            let settings = Settings::load(vars, &args(&["--client.token_env", "GAME_TOKEN"])).unwrap();
+
+            assert_eq!(settings.client_config().unwrap().token().unwrap().expose(), "hunter2");
+            expect_invalid(Settings::load(required(), &args(&["--client.token_env", "GAME_TOKEN"])), "client.token_env");
+        }
+
+        #[test]
+        fn missing_token_file_points_to_key() {
+            expect_invalid(Settings::load(required(), &args(&["--client.token_file", "/does/not/exist"])), "client.token_file");
+        }
+    }
+
+    #[cfg(test)]
+    mod errors {
+        use super::*;
+
+        #[test]
+        fn unknown_file_key_is_reported_with_path() {
+            let path = write_config("unknown.toml", "[client]\nnmae = \"typo\"\n");
+
+            let result = Settings::load(required(), &args(&["--config", path.to_str().unwrap()]));
+            match result {
+                Err(ConfigError::UnknownKey{ ref key }) if key == "client.nmae" => (),
+                _ => panic!("Expected an unknown key error but got {:?}", result),
+            }
+        }
+
+        #[test]
+        fn wrong_type_in_file_points_to_key() {
+            let path = write_config("type.toml", "[timeouts]\naction_ms = \"soon\"\n");
+
+            let result = Settings::load(required(), &args(&["--config", path.to_str().unwrap()]));
+            expect_invalid(result, "timeouts.action_ms");
+        }
+
+        #[test]
+        fn wrong_type_in_env_points_to_key() {
+            let mut vars = required();
+            vars.extend(env(&[("WARTEMIS_ROUTING_CAPACITY", "lots")]));
+
+            expect_invalid(Settings::load(vars, &[]), "routing.capacity");
+        }
+
+        #[test]
+        fn unknown_overflow_policy_points_to_key() {
+            expect_invalid(Settings::load(required(), &args(&["--routing.overflow", "explode"])), "routing.overflow");
+        }
+
+        #[test]
+        fn unknown_cli_flag_is_rejected() {
+            let result = Settings::load(required(), &args(&["--client.colour", "red"]));
+            match result {
+                Err(ConfigError::UnknownKey{ ref key }) if key == "client.colour" => (),
+                _ => panic!("Expected an unknown key error but got {:?}", result),
+            }
+        }
+
+        #[test]
+        fn cli_flag_without_value_is_rejected() {
+            let result = Settings::load(required(), &args(&["--client.name"]));
+            match result {
+                Err(ConfigError::MissingValue{ .. }) => (),
+                _ => panic!("Expected a missing value error but got {:?}", result),
+            }
+        }
+
+        #[test]
+        fn validation_points_to_key() {
+            expect_invalid(Settings::load(vec![], &[]), "client.game");
+            expect_invalid(Settings::load(required(), &args(&["--client.name", "my bot"])), "client.name");
+            expect_invalid(Settings::load(required(), &args(&["--client.client_type", "robot"])), "client.client_type");
+            expect_invalid(Settings::load(required(), &args(&["--server.url", "http://x"])), "server.url");
+            expect_invalid(Settings::load(required(), &args(&["--logging.level", "loud"])), "logging.level");
+        }
+
+        #[test]
+        fn missing_file_is_reported() {
+            let result = Settings::load(required(), &args(&["--config", "/does/not/exist.toml"]));
+            assert!(matches!(result, Err(ConfigError::Read{ .. })), "{:?}", result);
+        }
+    }
+}
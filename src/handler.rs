@@ -1,16 +1,25 @@
 use std::collections::HashMap;
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::channel;
+use crate::client_config::ClientType;
+pub use crate::client_config::ClientConfig;
 use crate::message as msg;
+use crate::validate;
 
 #[derive(Hash,PartialEq,Eq,Debug,Clone)]
 pub enum Outputs {
     Server,
     Bot,
+    /// Receives the game as seen by a `ClientType::Viewer`.
+    Viewer,
 }
 
 pub enum Response {
     SetID(i32),
+    /// The handler answered a `State` with an `Action` itself.
+    Action,
     Empty
 }
 
@@ -22,6 +31,9 @@ pub enum HandleError {
     #[error("no output defined for outputType `{0:?}`")]
     UndefinedOutput(Outputs),
 
+    #[error("client type `{0}` can't send actions")]
+    ActionNotAllowed(ClientType),
+
     #[error(transparent)]
     MessageError(#[from] msg::MessageError),
 
@@ -30,18 +42,66 @@ pub enum HandleError {
         source: crossbeam_channel::SendError<String>,
         output: Outputs,
         },
+
+    #[error("send message to {output:?}: {reason}")]
+    SinkError{
+        reason: String,
+        output: Outputs,
+        },
+}
+
+impl HandleError {
+    /// Name of the variant, used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HandleError::UnknownMessageType(_) => "UnknownMessageType",
+            HandleError::UndefinedOutput(_) => "UndefinedOutput",
+            HandleError::ActionNotAllowed(_) => "ActionNotAllowed",
+            HandleError::MessageError(_) => "MessageError",
+            HandleError::SendError{..} => "SendError",
+            HandleError::SinkError{..} => "SinkError",
+        }
+    }
 }
 
-pub trait Handler {
-    fn handle(&self, json: String, msg_type: msg::Message) -> Result<Response,HandleError>;
+/// Handles messages whose `State` and `Action` are of type `S` and `A`.
+pub trait Handler<S = msg::MessageContent, A = msg::MessageContent> {
+    fn handle(&self, json: String, msg_type: msg::Message<S, A>) -> Result<Response,HandleError>;
+    fn add_output(&mut self, output_type: Outputs, output: channel::OutputSender);
+
+    /// Called every tick when the client has a tick interval, for work that
+    /// isn't triggered by a message.
+    fn tick(&self) -> Result<Response,HandleError> {
+        Ok(Response::Empty)
+    }
+
+    /// How soon the handler needs a `tick`, also without a tick interval.
+    fn next_tick(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Send a message the client made itself, like a heartbeat.
+    fn inject(&self, _json: String, output: Outputs) -> Result<Response,HandleError> {
+        Err(HandleError::UndefinedOutput(output))
+    }
+
+    /// Configuration the handler registers with, used as logging context.
+    fn client_config(&self) -> Option<&ClientConfig> {
+        None
+    }
+
+    /// Add a plain channel as output, a full channel blocks the handler.
     fn add_output_channel(&mut self,
         output_type: Outputs,
-        channel: crossbeam_channel::Sender<String>);
+        channel: crossbeam_channel::Sender<String>) {
+        self.add_output(output_type, channel.into());
+    }
 }
 
 pub struct MessageHandler {
     client_config: ClientConfig,
-    outputs: HashMap<Outputs, crossbeam_channel::Sender<String>>,
+    outputs: HashMap<Outputs, channel::OutputSender>,
+    action_validator: Option<Box<dyn validate::ActionValidator>>,
 }
 
 
@@ -54,14 +114,25 @@ impl Handler for MessageHandler {
             // pass to client
             msg::Message::Error(_) => self.handle_error(json),
             msg::Message::State(_) => self.handle_state(json),
+            msg::Message::GameEnd(_) => self.handle_game_end(json),
             // pass to server
-            msg::Message::Action(_) => self.handle_action(json),
+            msg::Message::Action(action) => self.handle_action(json, action),
+            // keepalive, the client answers pings on the side they came from
+            msg::Message::Pong(_) => Ok(Response::Empty),
             _ => Err(HandleError::UnknownMessageType(msg_type)),
         }
     }
 
-    fn add_output_channel(&mut self, output_type: Outputs, channel: crossbeam_channel::Sender<String>){
-        self.outputs.insert(output_type, channel);
+    fn add_output(&mut self, output_type: Outputs, output: channel::OutputSender){
+        self.outputs.insert(output_type, output);
+    }
+
+    fn client_config(&self) -> Option<&ClientConfig> {
+        Some(&self.client_config)
+    }
+
+    fn inject(&self, json: String, output: Outputs) -> Result<Response,HandleError> {
+        self.send(json, &output)
     }
 }
 
@@ -70,20 +141,26 @@ impl MessageHandler {
         MessageHandler{
             client_config: client_config,
             outputs: HashMap::new(),
+            action_validator: None,
         }
     }
 
-    fn build_register_message(&self) -> Result<String, msg::MessageError> {
-        let register_msg = msg::Message::Register(msg::Register {
-            clientType: self.client_config.clientType.clone(),
-            game: self.client_config.game.clone(),
-            name: self.client_config.name.clone(),
-        });
-        msg::serialize_message(register_msg)
+    /// Check every action before it is sent to the server. Invalid actions
+    /// are answered with a local `Error` message to the bot.
+    pub fn set_action_validator(&mut self, validator: Box<dyn validate::ActionValidator>) {
+        self.action_validator = Some(validator);
+    }
+
+    pub(crate) fn check_action(&self, action: &msg::MessageContent) -> Result<(), msg::MessageContent> {
+        match &self.action_validator {
+            Some(validator) => validator.validate(&action.content)
+                .map_err(|reason| validate::invalid_action_error(&reason)),
+            None => Ok(()),
+        }
     }
 
     fn handle_connected(&self) -> Result<Response, HandleError> {
-        let register_msg = self.build_register_message()?;
+        let register_msg = build_register_message(&self.client_config)?;
         self.send(register_msg, &Outputs::Server)
     }
 
@@ -92,18 +169,29 @@ impl MessageHandler {
     }
 
     fn handle_error(&self, m: String) -> Result<Response, HandleError> {
-        self.send(m, &Outputs::Bot)
+        self.send(m, &client_output(&self.client_config))
     }
 
     fn handle_state(&self, m: String) -> Result<Response, HandleError> {
-        self.send(m, &Outputs::Bot)
+        self.send(m, &client_output(&self.client_config))
+    }
+
+    fn handle_game_end(&self, m: String) -> Result<Response, HandleError> {
+        self.send(m, &client_output(&self.client_config))
     }
 
-    fn handle_action(&self, m: String) -> Result<Response, HandleError> {
+    fn handle_action(&self, m: String, action: msg::MessageContent) -> Result<Response, HandleError> {
+        check_action_allowed(&self.client_config)?;
+        if let Err(error) = self.check_action(&action) {
+            tracing::debug!(error = ?error.content, "rejecting invalid action");
+            let error_msg = msg::serialize_message(msg::Message::Error(error))?;
+            return self.send(error_msg, &Outputs::Bot);
+        }
         self.send(m, &Outputs::Server)
     }
 
-    fn send(&self, m: String, output: &Outputs) -> Result<Response, HandleError> {
+    pub(crate) fn send(&self, m: String, output: &Outputs) -> Result<Response, HandleError> {
+        tracing::Span::current().record("route", tracing::field::debug(output));
         let chan = self.outputs.get(output)
             .ok_or(HandleError::UndefinedOutput(output.clone()))?;
 
@@ -116,10 +204,30 @@ impl MessageHandler {
     }
 }
 
-pub struct ClientConfig{
-    pub clientType: String,
-    pub game: String,
-    pub name: String,
+/// Output that receives the messages meant for the client itself.
+fn client_output(client_config: &ClientConfig) -> Outputs {
+    match client_config.client_type() {
+        ClientType::Bot => Outputs::Bot,
+        ClientType::Viewer => Outputs::Viewer,
+    }
+}
+
+/// A viewer only watches, it never forwards actions to the server.
+fn check_action_allowed(client_config: &ClientConfig) -> Result<(), HandleError> {
+    match client_config.client_type() {
+        ClientType::Bot => Ok(()),
+        client_type => Err(HandleError::ActionNotAllowed(client_type)),
+    }
+}
+
+fn build_register_message(client_config: &ClientConfig) -> Result<String, msg::MessageError> {
+    let register_msg = msg::Message::Register(msg::Register {
+        clientType: client_config.client_type().to_string(),
+        game: client_config.game().to_string(),
+        name: client_config.name().to_string(),
+        token: client_config.token().cloned(),
+    });
+    msg::serialize_message(register_msg)
 }
 
 #[cfg(test)]
@@ -128,11 +236,11 @@ mod tests {
     use std::matches;
 
     fn default_client_config() -> ClientConfig {
-        ClientConfig{
-            clientType: "bot".to_string(),
-            game: "test_game".to_string(),
-            name: "test_bot".to_string(),
-        }
+        ClientConfig::builder()
+            .game("test_game")
+            .name("test_bot")
+            .build()
+            .unwrap()
     }
 
     struct HandleResult{
@@ -199,13 +307,32 @@ mod tests {
             assert_eq!(ok, true);
         }
 
+        #[test]
+        fn handle_msg_connected_sends_token_in_register() {
+            let config = ClientConfig::builder()
+                .game("test_game")
+                .name("test_bot")
+                .token(crate::secret::Secret::new("hunter2"))
+                .build()
+                .unwrap();
+            let mut handler = MessageHandler::new(config);
+            let (sender, receiver) = crossbeam_channel::bounded(1);
+            handler.add_output_channel(Outputs::Server, sender);
+
+            let message_json = r#"{"type": "Connected"}"#;
+            handler.handle(message_json.to_string(), msg::deserialize_message(message_json).unwrap()).unwrap();
+
+            assert!(receiver.recv().unwrap().ends_with(r#","token":"hunter2"}"#));
+        }
+
         #[test]
         fn handle_msg_connected_get_empty_response_and_send_register() {
             let message_json = r#"{"type": "Connected"}"#.to_string();
             let response_message = msg::Message::Register(msg::Register{
-                clientType: default_client_config().clientType,
-                game: default_client_config().game,
-                name: default_client_config().name,
+                clientType: default_client_config().client_type().to_string(),
+                game: default_client_config().game().to_string(),
+                name: default_client_config().name().to_string(),
+                token: None,
             });
             let expected_channel_response = msg::serialize_message(response_message).unwrap();
 
@@ -241,6 +368,16 @@ mod tests {
                 target_output_channel);
         }
 
+        #[test]
+        fn handle_game_end_as_proxy() {
+            let message_json = r#"{"type": "GameEnd"}"#;
+            let target_output_channel = Outputs::Bot;
+
+            handle_message_as_proxy_and_expect_empty_response(
+                message_json.to_string(),
+                target_output_channel);
+        }
+
         #[test]
         fn handle_action_as_proxy() {
             let message_json = r#"{"type": "Action"}"#;
@@ -253,6 +390,136 @@ mod tests {
 
     }
 
+    #[cfg(test)]
+    mod heartbeat {
+        use super::*;
+
+        #[test]
+        fn pong_is_not_forwarded() {
+            let handler = MessageHandler::new(default_client_config());
+            let json = r#"{"type":"Pong"}"#;
+            let response = handler.handle(json.to_string(), msg::deserialize_message(json).unwrap());
+            assert!(matches!(response, Ok(Response::Empty)));
+        }
+
+        #[test]
+        fn injected_message_is_sent_as_is() {
+            let mut handler = MessageHandler::new(default_client_config());
+            let (sender, receiver) = crossbeam_channel::bounded(1);
+            handler.add_output_channel(Outputs::Server, sender);
+
+            handler.inject(r#"{"type":"Ping"}"#.to_string(), Outputs::Server).unwrap();
+            assert_eq!(receiver.recv().unwrap(), r#"{"type":"Ping"}"#);
+        }
+    }
+
+    #[cfg(test)]
+    mod viewer {
+        use super::*;
+
+        fn viewer_handler() -> (MessageHandler, crossbeam_channel::Receiver<String>, crossbeam_channel::Receiver<String>) {
+            let config = ClientConfig::builder()
+                .client_type(ClientType::Viewer)
+                .game("test_game")
+                .name("test_viewer")
+                .build()
+                .unwrap();
+            let mut handler = MessageHandler::new(config);
+
+            let (server_snd, server_rec) = crossbeam_channel::unbounded();
+            let (viewer_snd, viewer_rec) = crossbeam_channel::unbounded();
+            handler.add_output_channel(Outputs::Server, server_snd);
+            handler.add_output_channel(Outputs::Viewer, viewer_snd);
+            (handler, server_rec, viewer_rec)
+        }
+
+        fn handle_json(handler: &MessageHandler, json: &str) -> Result<Response, HandleError> {
+            handler.handle(json.to_string(), msg::deserialize_message(json).unwrap())
+        }
+
+        #[test]
+        fn viewer_registers_as_viewer() {
+            let (handler, server_rec, _) = viewer_handler();
+
+            handle_json(&handler, r#"{"type": "Connected"}"#).unwrap();
+
+            assert_eq!(
+                server_rec.try_recv().unwrap(),
+                r#"{"type":"Register","clientType":"viewer","game":"test_game","name":"test_viewer"}"#);
+        }
+
+        #[test]
+        fn viewer_streams_every_state() {
+            let (handler, _, viewer_rec) = viewer_handler();
+
+            for turn in 0..3 {
+                handle_json(&handler, &format!(r#"{{"type": "State", "turn": {}}}"#, turn)).unwrap();
+            }
+            handle_json(&handler, r#"{"type": "GameEnd"}"#).unwrap();
+
+            let received: Vec<String> = viewer_rec.try_iter().collect();
+            assert_eq!(received.len(), 4);
+            assert_eq!(received[2], r#"{"type": "State", "turn": 2}"#);
+        }
+
+        #[test]
+        fn viewer_never_forwards_actions() {
+            let (handler, server_rec, _) = viewer_handler();
+
+            let returned_err = handle_json(&handler, r#"{"type": "Action"}"#).err().unwrap();
+            match returned_err {
+                HandleError::ActionNotAllowed(ClientType::Viewer) => (),
+                _ => panic!("Expected an ActionNotAllowed error but got {:?}", returned_err),
+            }
+            assert!(server_rec.try_recv().is_err());
+        }
+    }
+
+    #[cfg(test)]
+    mod validation {
+        use super::*;
+
+        fn handler_with_validator() -> (MessageHandler, crossbeam_channel::Receiver<String>, crossbeam_channel::Receiver<String>) {
+            let mut handler = MessageHandler::new(default_client_config());
+            handler.set_action_validator(Box::new(|action: &serde_json::Value| {
+                match action.get("move") {
+                    Some(_) => Ok(()),
+                    None => Err("missing `move`".to_string()),
+                }
+            }));
+
+            let (server_snd, server_rec) = crossbeam_channel::bounded(1);
+            let (bot_snd, bot_rec) = crossbeam_channel::bounded(1);
+            handler.add_output_channel(Outputs::Server, server_snd);
+            handler.add_output_channel(Outputs::Bot, bot_snd);
+            (handler, server_rec, bot_rec)
+        }
+
+        #[test]
+        fn valid_action_is_sent_to_server() {
+            let (handler, server_rec, bot_rec) = handler_with_validator();
+            let msg_json = r#"{"type": "Action", "move": 1}"#;
+
+            handler.handle(msg_json.to_string(), msg::deserialize_message(msg_json).unwrap()).unwrap();
+
+            assert_eq!(server_rec.try_recv().unwrap(), msg_json);
+            assert!(bot_rec.try_recv().is_err());
+        }
+
+        #[test]
+        fn invalid_action_is_returned_to_bot_as_error() {
+            let (handler, server_rec, bot_rec) = handler_with_validator();
+            let msg_json = r#"{"type": "Action", "stay": true}"#;
+
+            handler.handle(msg_json.to_string(), msg::deserialize_message(msg_json).unwrap()).unwrap();
+
+            assert!(server_rec.try_recv().is_err());
+            assert_eq!(
+                bot_rec.try_recv().unwrap(),
+                r#"{"type":"Error","message":"invalid action: missing `move`"}"#);
+        }
+    }
+
     #[cfg(test)]
     mod errors {
         use super::*;
@@ -263,6 +530,7 @@ mod tests {
                 clientType: "x".to_string(),
                 game: "y".to_string(),
                 name: "z".to_string(),
+                token: None,
             };
             let msg_json = msg::serialize_message(msg::Message::Register(register_msg.clone())).unwrap();
 
@@ -310,5 +578,27 @@ mod tests {
                 _ => panic!("Expected a send error but got {:?}", returned_err),
             }
         }
+
+        #[test]
+        fn trigger_send_error_by_overflowing_disconnect_output() {
+            let mut handler = MessageHandler::new(default_client_config());
+
+            let config = channel::ChannelConfig{
+                capacity: 1,
+                policy: channel::OverflowPolicy::Disconnect,
+            };
+            let (output, _receiver) = channel::bounded(&config);
+            handler.add_output(Outputs::Bot, output);
+
+            let state = || msg::deserialize_message(r#"{"type": "State"}"#).unwrap();
+            handler.handle(r#"{"type": "State"}"#.to_string(), state()).unwrap();
+            let response = handler.handle(r#"{"type": "State"}"#.to_string(), state());
+
+            let returned_err = response.err().unwrap();
+            match returned_err {
+                HandleError::SendError{ source: _, output: Outputs::Bot} => (),
+                _ => panic!("Expected a send error but got {:?}", returned_err),
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::handler::Outputs;
+use crate::message as msg;
+
+/// What the client does when the server or the bot has been silent for too
+/// long.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnDeadPeer {
+    /// Close the connection and connect again, which restarts a bot process.
+    #[default]
+    Reconnect,
+    /// Stop the client.
+    Shutdown,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct HeartbeatConfig {
+    /// Time between two `Ping` messages to the peer.
+    pub interval: Duration,
+    /// The peer counts as dead when nothing came in for this long.
+    pub timeout: Duration,
+    pub on_dead_peer: OnDeadPeer,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig{
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+            on_dead_peer: OnDeadPeer::Reconnect,
+        }
+    }
+}
+
+/// Keeps track of when a peer was last heard from.
+pub struct Liveness {
+    timeout: Duration,
+    last_seen: Mutex<Option<Instant>>,
+}
+
+impl Liveness {
+    pub fn new(timeout: Duration) -> Self {
+        Liveness{ timeout, last_seen: Mutex::new(None) }
+    }
+
+    pub fn seen(&self, now: Instant) {
+        *self.last_seen.lock().unwrap() = Some(now);
+    }
+
+    pub fn last_seen(&self) -> Option<Instant> {
+        *self.last_seen.lock().unwrap()
+    }
+
+    /// A peer that was never seen is alive.
+    pub fn is_alive(&self, now: Instant) -> bool {
+        match self.last_seen() {
+            Some(last_seen) => now.saturating_duration_since(last_seen) < self.timeout,
+            None => true,
+        }
+    }
+}
+
+/// Answer to `ping`, with the same content.
+pub fn pong(ping: msg::MessageContent) -> Result<String, msg::MessageError> {
+    msg::serialize_message(msg::Message::Pong(ping))
+}
+
+/// Answer a received `ping` on `source`, the side it came from, which the
+/// handler can't tell. `inject` sends the `Pong`, so the sync and the async
+/// client answer the same way.
+pub fn answer<R>(ping: msg::MessageContent, source: Outputs, inject: impl FnOnce(String, Outputs) -> R) -> R {
+    let pong = pong(ping).expect("a received ping serializes");
+    inject(pong, source)
+}
+
+pub fn ping() -> String {
+    msg::serialize_message(msg::Message::Ping(msg::MessageContent{ content: serde_json::json!({}) }))
+        .expect("a ping always serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_dies_after_the_timeout() {
+        let liveness = Liveness::new(Duration::from_secs(3));
+        let start = Instant::now();
+        assert!(liveness.is_alive(start + Duration::from_secs(60)));
+
+        liveness.seen(start);
+        assert!(liveness.is_alive(start + Duration::from_millis(2999)));
+        assert!(!liveness.is_alive(start + Duration::from_secs(3)));
+
+        liveness.seen(start + Duration::from_secs(3));
+        assert!(liveness.is_alive(start + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn ping_has_no_content() {
+        assert_eq!(ping(), r#"{"type":"Ping"}"#);
+    }
+
+    #[test]
+    fn pong_keeps_the_ping_content() {
+        let ping = msg::MessageContent{ content: serde_json::json!({"seq": 4}) };
+        assert_eq!(pong(ping).unwrap(), r#"{"type":"Pong","seq":4}"#);
+    }
+
+    #[test]
+    fn answer_goes_back_to_the_source() {
+        let ping = msg::MessageContent{ content: serde_json::json!({"seq": 4}) };
+        let (pong, output) = answer(ping, Outputs::Bot, |pong, output| (pong, output));
+        assert_eq!(pong, r#"{"type":"Pong","seq":4}"#);
+        assert_eq!(output, Outputs::Bot);
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::Arc;
+use std::thread;
+
+use thiserror::Error;
+
+use crate::channel;
+use crate::client::Client;
+use crate::handler::{self, ClientConfig};
+use crate::transport::{Transport, TransportError};
+
+#[derive(Error,Debug)]
+pub enum HostError {
+    #[error("a bot named `{0}` is already hosted")]
+    DuplicateName(String),
+
+    #[error("start bot `{name}`: {source}")]
+    Start{
+        name: String,
+        source: TransportError,
+    },
+}
+
+/// One bot registration: what it registers as and how it reaches the server
+/// and its bot. Several registrations can share a server connection through
+/// a `transport::multiplex::Multiplexer`.
+pub struct Registration {
+    pub client_config: ClientConfig,
+    pub server: Arc<dyn Transport>,
+    pub bot: Arc<dyn Transport>,
+}
+
+struct Hosted {
+    name: String,
+    server: Arc<dyn Transport>,
+    bot: Arc<dyn Transport>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// Runs several bot registrations in one process. Every registration gets
+/// its own client on its own thread, so its id, routing and lifecycle are
+/// separate from the others.
+pub struct Host {
+    channel_config: channel::ChannelConfig,
+    hosted: Vec<Hosted>,
+}
+
+impl Host {
+    pub fn new(channel_config: channel::ChannelConfig) -> Self {
+        Host{
+            channel_config,
+            hosted: Vec::new(),
+        }
+    }
+
+    /// Connect the registration's transports and start its client.
+    pub fn add(&mut self, registration: Registration) -> Result<(), HostError> {
+        let name = registration.client_config.name().to_string();
+        if self.hosted.iter().any(|hosted| hosted.name == name) {
+            return Err(HostError::DuplicateName(name));
+        }
+
+        let server = registration.server.clone();
+        let bot = registration.bot.clone();
+        let config = self.channel_config.clone();
+        let (started_sender, started) = crossbeam_channel::bounded(1);
+
+        // The client is built on its own thread, it can't be moved there.
+        let thread = thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(registration.client_config));
+            let client: Client = match Client::with_transports(handler, registration.server, registration.bot, &config) {
+                Ok(client) => client,
+                Err(e) => {
+                    let _ = started_sender.send(Err(e));
+                    return;
+                },
+            };
+            let _ = started_sender.send(Ok(()));
+            let _ = client.start();
+        });
+
+        match started.recv() {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => return Err(HostError::Start{ name, source: e }),
+            Err(_) => return Err(HostError::Start{ name, source: TransportError::Closed }),
+        }
+
+        tracing::info!(bot = %name, "hosting bot");
+        self.hosted.push(Hosted{ name, server, bot, thread });
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.hosted.iter().map(|hosted| hosted.name.as_str()).collect()
+    }
+
+    /// Close the transports of one bot and wait for its client to stop. The
+    /// other bots are not affected.
+    pub fn stop(&mut self, name: &str) -> bool {
+        let index = match self.hosted.iter().position(|hosted| hosted.name == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        stop(self.hosted.remove(index));
+        true
+    }
+
+    /// Stop every bot.
+    pub fn shutdown(mut self) {
+        for hosted in self.hosted.drain(..) {
+            stop(hosted);
+        }
+    }
+}
+
+fn stop(hosted: Hosted) {
+    for transport in &[&hosted.server, &hosted.bot] {
+        if let Err(e) = transport.close() {
+            tracing::warn!(bot = %hosted.name, error = %e, "close transport");
+        }
+    }
+    if hosted.thread.join().is_err() {
+        tracing::warn!(bot = %hosted.name, "client panicked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+    use crate::transport::multiplex::Multiplexer;
+    use serde_json::{json, Value};
+
+    fn config(name: &str) -> ClientConfig {
+        ClientConfig::builder().game("test_game").name(name).build().unwrap()
+    }
+
+    /// A registration with its own server and bot connection, returning the
+    /// far ends of both.
+    fn registration(name: &str) -> (Registration, MemoryTransport, MemoryTransport) {
+        let (server, server_end) = MemoryTransport::pair();
+        let (bot, bot_end) = MemoryTransport::pair();
+        server_end.connect().unwrap();
+        bot_end.connect().unwrap();
+        let registration = Registration{
+            client_config: config(name),
+            server: Arc::new(server),
+            bot: Arc::new(bot),
+        };
+        (registration, server_end, bot_end)
+    }
+
+    fn receive(transport: &dyn Transport) -> Value {
+        serde_json::from_str(&transport.receive().unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bots_on_separate_connections_are_isolated() {
+        let mut host = Host::new(channel::ChannelConfig::default());
+        let (first, first_server, first_bot) = registration("first");
+        let (second, second_server, second_bot) = registration("second");
+        host.add(first).unwrap();
+        host.add(second).unwrap();
+        assert_eq!(host.names(), vec!["first", "second"]);
+
+        second_server.send(r#"{"type":"State","turn":1}"#).unwrap();
+        assert_eq!(receive(&second_bot), json!({"type": "State", "turn": 1}));
+
+        assert!(host.stop("first"));
+        assert_eq!(first_server.receive().unwrap(), None);
+        assert_eq!(first_bot.receive().unwrap(), None);
+
+        second_bot.send(r#"{"type":"Action","move":"up"}"#).unwrap();
+        assert_eq!(receive(&second_server), json!({"type": "Action", "move": "up"}));
+        host.shutdown();
+    }
+
+    #[test]
+    fn bots_share_a_multiplexed_connection() {
+        let (shared, server) = MemoryTransport::pair();
+        server.connect().unwrap();
+        let mux = Multiplexer::new(Arc::new(shared));
+
+        let mut host = Host::new(channel::ChannelConfig::default());
+        let mut bots = Vec::new();
+        for (slot, name) in ["first", "second"].iter().enumerate() {
+            let (bot, bot_end) = MemoryTransport::pair();
+            bot_end.connect().unwrap();
+            bots.push(bot_end);
+            host.add(Registration{
+                client_config: config(name),
+                server: Arc::new(mux.slot(slot as u32)),
+                bot: Arc::new(bot),
+            }).unwrap();
+        }
+
+        // Both registrations answer the shared `Connected` on their own slot.
+        server.send(r#"{"type":"Connected"}"#).unwrap();
+        let mut registers = [receive(&server), receive(&server)];
+        registers.sort_by_key(|register| register["slot"].as_u64());
+        assert_eq!(registers[0], json!({"type": "Register", "clientType": "bot", "game": "test_game", "name": "first", "slot": 0}));
+        assert_eq!(registers[1]["name"], "second");
+
+        server.send(r#"{"type":"State","turn":3,"slot":1}"#).unwrap();
+        assert_eq!(receive(&bots[1]), json!({"type": "State", "turn": 3}));
+
+        bots[1].send(r#"{"type":"Action","move":"left"}"#).unwrap();
+        assert_eq!(receive(&server), json!({"type": "Action", "move": "left", "slot": 1}));
+        host.shutdown();
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let mut host = Host::new(channel::ChannelConfig::default());
+        host.add(registration("bot").0).unwrap();
+        assert!(matches!(host.add(registration("bot").0), Err(HostError::DuplicateName(_))));
+        host.shutdown();
+    }
+
+    #[test]
+    fn failing_connection_is_reported() {
+        let (server, _server_end) = MemoryTransport::pair();
+        server.close().unwrap();
+        let (bot, _bot_end) = MemoryTransport::pair();
+
+        let mut host = Host::new(channel::ChannelConfig::default());
+        let result = host.add(Registration{ client_config: config("bot"), server: Arc::new(server), bot: Arc::new(bot) });
+        assert!(matches!(result, Err(HostError::Start{ .. })));
+        assert!(host.names().is_empty());
+    }
+}
@@ -1,6 +1,26 @@
+mod bot;
+mod channel;
+mod config;
 mod message;
 mod client;
+mod clock;
+mod client_config;
 mod handler;
+mod heartbeat;
+mod host;
+mod metrics;
+mod middleware;
+mod rate_limit;
+mod referee;
+mod rules;
+mod secret;
+mod tournament;
+mod transport;
+mod validate;
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "tls")]
+mod tls;
 
 fn main() {
     // let client_config = client::ClientConfig
@@ -2,8 +2,11 @@ use std::fmt;
 use serde_json::Value;
 use serde_json;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
+use crate::secret::Secret;
+
 #[derive(Error,Debug)]
 pub enum MessageError {
 	#[error("deserialise message: {source}")]
@@ -11,39 +14,128 @@ pub enum MessageError {
 
 	#[error("serialise message: {source}")]
     Serialize{ source: serde_json::Error},
+
+	#[error("deserialise message: at `{path}`: {source}")]
+    Payload{ path: String, source: serde_json::Error},
+
+	#[error("malformed frame: {0}")]
+    Frame(String),
+
+	#[error("{0} content is not a JSON object")]
+    NotAnObject(&'static str),
 }
 
 #[serde(tag = "type")]
-#[derive(Serialize, Deserialize,Debug,PartialEq)]
-pub enum Message {
+#[derive(Serialize, Deserialize,Debug,PartialEq,Clone)]
+pub enum Message<S = MessageContent, A = MessageContent> {
 	Connected		(Connected),
 	RegisterSuccess (RegisterSuccess),
 	Register 		(Register),
-	Action			(MessageContent),
+	Action			(A),
 	Error 			(MessageContent),
-	State 			(MessageContent),
+	State 			(S),
+	GameEnd 		(MessageContent),
+	/// Keepalive, answered with a `Pong` carrying the same content.
+	Ping 			(MessageContent),
+	Pong 			(MessageContent),
+}
+
+impl<S, A> Message<S, A> {
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Message::Connected(_) => "Connected",
+			Message::RegisterSuccess(_) => "RegisterSuccess",
+			Message::Register(_) => "Register",
+			Message::Action(_) => "Action",
+			Message::Error(_) => "Error",
+			Message::State(_) => "State",
+			Message::GameEnd(_) => "GameEnd",
+			Message::Ping(_) => "Ping",
+			Message::Pong(_) => "Pong",
+		}
+	}
+}
+
+impl<S: Serialize, A: Serialize> Message<S, A> {
+	/// Replace a typed `State` or `Action` by its untyped content. Both must
+	/// serialise to a JSON object, as their fields become the message fields.
+	pub fn into_untyped(self) -> Result<Message, MessageError> {
+		Ok(match self {
+			Message::Connected(m) => Message::Connected(m),
+			Message::RegisterSuccess(m) => Message::RegisterSuccess(m),
+			Message::Register(m) => Message::Register(m),
+			Message::Action(a) => Message::Action(MessageContent::from_typed("Action", &a)?),
+			Message::Error(m) => Message::Error(m),
+			Message::State(s) => Message::State(MessageContent::from_typed("State", &s)?),
+			Message::GameEnd(m) => Message::GameEnd(m),
+			Message::Ping(m) => Message::Ping(m),
+			Message::Pong(m) => Message::Pong(m),
+		})
+	}
 }
 
-#[derive(Serialize, Deserialize,Debug,PartialEq)]
+#[derive(Serialize, Deserialize,Debug,PartialEq,Clone)]
 pub struct MessageContent {
-	#[serde(skip_serializing,flatten)]
+	#[serde(flatten)]
 	pub content: Value,
 }
 
+impl MessageContent {
+	fn from_typed<T: Serialize>(kind: &'static str, typed: &T) -> Result<Self, MessageError> {
+		let content = serde_json::to_value(typed)
+			.map_err(|e| MessageError::Serialize{source: e})?;
+		if !content.is_object() {
+			return Err(MessageError::NotAnObject(kind));
+		}
+		Ok(MessageContent{ content })
+	}
+}
+
 pub fn deserialize_message(json: &str) -> Result<Message, MessageError> {
-	serde_json::from_str(json)
-		.map_err(|e| MessageError::Deserialize{source: e})
+	deserialize_typed_message(json)
+}
+
+/// Deserialise a message with game specific `State` and `Action` types. An
+/// invalid `State` or `Action` results in a `MessageError::Payload` with the
+/// path to the offending field.
+pub fn deserialize_typed_message<S, A>(json: &str) -> Result<Message<S, A>, MessageError>
+		where S: DeserializeOwned, A: DeserializeOwned {
+	let mut value: Value = serde_json::from_str(json)
+		.map_err(|e| MessageError::Deserialize{source: e})?;
+
+	let message_type = value.get("type").and_then(Value::as_str).map(str::to_string);
+	match message_type.as_deref() {
+		Some("State") => deserialize_payload(&mut value).map(Message::State),
+		Some("Action") => deserialize_payload(&mut value).map(Message::Action),
+		_ => serde_json::from_value(value)
+			.map_err(|e| MessageError::Deserialize{source: e}),
+	}
+}
+
+fn deserialize_payload<T: DeserializeOwned>(value: &mut Value) -> Result<T, MessageError> {
+	if let Value::Object(map) = value {
+		map.remove("type");
+	}
+	serde_path_to_error::deserialize(value.take())
+		.map_err(|e| MessageError::Payload{
+			path: e.path().to_string(),
+			source: e.into_inner(),
+		})
 }
 
 pub fn serialize_message(message: Message) -> Result<String, MessageError> {
+	serialize_typed_message(message)
+}
+
+pub fn serialize_typed_message<S: Serialize, A: Serialize>(message: Message<S, A>) -> Result<String, MessageError> {
 	serde_json::to_string(&message)
 		.map_err(|e| MessageError::Serialize{source: e})
 }
 
-#[derive(Serialize, Deserialize,Debug,PartialEq)]
+#[derive(Serialize, Deserialize,Debug,PartialEq,Clone)]
 pub struct Connected {}
 
-#[derive(Serialize, Deserialize,Debug,PartialEq)]
+#[derive(Serialize, Deserialize,Debug,PartialEq,Clone)]
 pub struct RegisterSuccess {
 	pub id: i32
 }
@@ -52,7 +144,9 @@ pub struct RegisterSuccess {
 pub struct Register {
 	pub clientType: String,
 	pub game: String,
-	pub name: String
+	pub name: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub token: Option<Secret>,
 }
 
 #[cfg(test)]
@@ -174,9 +268,24 @@ mod tests {
 				game: "game".to_string(),
 				name: "name".to_string(),
 				clientType: "clientType".to_string(),
+				token: None,
 			})
 		}
 
+		#[test]
+		fn message_register_with_token_serialize() {
+			let msg_struct = Message::Register(Register{
+				game: "game".to_string(),
+				name: "name".to_string(),
+				clientType: "bot".to_string(),
+				token: Some(Secret::new("hunter2")),
+			});
+
+			let json = serialize_message(msg_struct.clone()).unwrap();
+			assert_eq!(json, r#"{"type":"Register","clientType":"bot","game":"game","name":"name","token":"hunter2"}"#);
+			assert!(!format!("{:?}", msg_struct).contains("hunter2"));
+		}
+
 		fn get_string_message_register() -> &'static str {
 			r#"{
 				"type": "Register",
@@ -206,6 +315,12 @@ mod tests {
 			}
 		}
 
+		#[test]
+		fn message_action_serialize_keeps_content() {
+			let msg_struct = deserialize_message(r#"{"type": "Action", "move": 3}"#).unwrap();
+			assert_eq!(serialize_message(msg_struct).unwrap(), r#"{"type":"Action","move":3}"#);
+		}
+
 		fn get_object_message_action() -> Message {
 			let mut map = serde_json::Map::new();
 			Message::Error(
@@ -244,6 +359,12 @@ mod tests {
 			}
 		}
 
+		#[test]
+		fn message_error_serialize_keeps_content() {
+			let msg_struct = deserialize_message(r#"{"type": "Error", "message": "You messed up"}"#).unwrap();
+			assert_eq!(serialize_message(msg_struct).unwrap(), r#"{"type":"Error","message":"You messed up"}"#);
+		}
+
 		fn get_object_message_error() -> Message {
 			let mut map = serde_json::Map::new();
 			Message::Error(
@@ -280,6 +401,12 @@ mod tests {
 			}
 		}
 
+		#[test]
+		fn message_state_serialize_keeps_content() {
+			let msg_struct = deserialize_message(r#"{"type": "State", "turn": 1}"#).unwrap();
+			assert_eq!(serialize_message(msg_struct).unwrap(), r#"{"type":"State","turn":1}"#);
+		}
+
 		fn get_object_message_state() -> Message {
 			let mut map = serde_json::Map::new();
 			Message::State(
@@ -297,4 +424,107 @@ mod tests {
 			}"#
 		}
 	}
-}
\ No newline at end of file
+
+	#[cfg(test)]
+	mod game_end {
+		use super::*;
+
+		#[test]
+		fn message_game_end_deserialize() {
+			match deserialize_message(get_string_message_game_end()).unwrap() {
+				Message::GameEnd(..) => (),
+				_ => panic!("deserialise game end"),
+			}
+		}
+
+		#[test]
+		fn message_game_end_serialize_keeps_content() {
+			let msg_struct = deserialize_message(get_string_message_game_end()).unwrap();
+			let json = serialize_message(msg_struct).unwrap();
+			assert_eq!(json, r#"{"type":"GameEnd","winner":"name"}"#);
+		}
+
+		fn get_string_message_game_end() -> &'static str {
+			r#"{
+				"type": "GameEnd",
+				"winner": "name"
+			}"#
+		}
+	}
+
+	#[cfg(test)]
+	mod typed {
+		use super::*;
+
+		#[derive(Serialize, Deserialize,Debug,PartialEq)]
+		struct GameState {
+			turn: u32,
+			units: Vec<Unit>,
+		}
+
+		#[derive(Serialize, Deserialize,Debug,PartialEq)]
+		struct Unit {
+			x: i32,
+			y: i32,
+		}
+
+		#[derive(Serialize, Deserialize,Debug,PartialEq)]
+		struct GameAction {
+			unit: usize,
+		}
+
+		type GameMessage = Message<GameState, GameAction>;
+
+		#[test]
+		fn message_typed_state_deserialize() {
+			let msg: GameMessage = deserialize_typed_message(
+				r#"{"type": "State", "turn": 2, "units": [{"x": 1, "y": 2}]}"#).unwrap();
+
+			assert_eq!(msg, Message::State(GameState{
+				turn: 2,
+				units: vec![Unit{ x: 1, y: 2 }],
+			}));
+		}
+
+		#[test]
+		fn message_typed_action_serialize() {
+			let msg: GameMessage = Message::Action(GameAction{ unit: 3 });
+			let json = serialize_typed_message(msg).unwrap();
+			assert_eq!(json, r#"{"type":"Action","unit":3}"#);
+		}
+
+		#[test]
+		fn message_typed_untyped_messages_still_deserialize() {
+			let msg: GameMessage = deserialize_typed_message(r#"{"type": "RegisterSuccess", "id": 1}"#).unwrap();
+			assert_eq!(msg, Message::RegisterSuccess(RegisterSuccess{ id: 1 }));
+		}
+
+		#[test]
+		fn deserialising_invalid_typed_state_should_point_to_field() {
+			let err = deserialize_typed_message::<GameState, GameAction>(
+				r#"{"type": "State", "turn": 2, "units": [{"x": 1, "y": "two"}]}"#);
+			match err {
+				Err(MessageError::Payload{ ref path, .. }) => assert_eq!(path, "units[0].y"),
+				_ => panic!("Expected a payload error but got {:?}", err),
+			}
+		}
+
+		#[test]
+		fn message_typed_into_untyped() {
+			let msg: GameMessage = Message::Action(GameAction{ unit: 3 });
+			match msg.into_untyped().unwrap() {
+				Message::Action(content) => assert_eq!(content.content["unit"], 3),
+				other => panic!("Expected an untyped action but got {:?}", other),
+			}
+		}
+
+		#[test]
+		fn message_typed_into_untyped_needs_an_object() {
+			let msg: Message<GameState, u32> = Message::Action(3);
+			match msg.into_untyped() {
+				Err(MessageError::NotAnObject(kind)) => assert_eq!(kind, "Action"),
+				other => panic!("Expected a not an object error but got {:?}", other),
+			}
+		}
+	}
+}
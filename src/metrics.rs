@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const MESSAGES_TOTAL: &str = "wartemis_messages_total";
+pub const HANDLE_ERRORS_TOTAL: &str = "wartemis_handle_errors_total";
+pub const STATE_TO_ACTION_SECONDS: &str = "wartemis_state_to_action_seconds";
+pub const UNRESPONSIVE_PEERS_TOTAL: &str = "wartemis_unresponsive_peers_total";
+
+/// How long the Prometheus endpoint waits for a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+/// Receives the metrics produced by the client.
+pub trait Recorder: Send + Sync {
+    fn increment_counter(&self, name: &'static str, labels: Labels);
+    fn record_histogram(&self, name: &'static str, labels: Labels, value: f64);
+}
+
+/// Recorder that throws everything away.
+pub struct NoopRecorder;
+
+impl Recorder for NoopRecorder {
+    fn increment_counter(&self, _name: &'static str, _labels: Labels) {}
+    fn record_histogram(&self, _name: &'static str, _labels: Labels, _value: f64) {}
+}
+
+type Key = (&'static str, Vec<(&'static str, String)>);
+
+fn key(name: &'static str, labels: Labels) -> Key {
+    let mut labels: Vec<_> = labels.iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect();
+    labels.sort();
+    (name, labels)
+}
+
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Histogram {
+    /// Cumulative count per bucket in `BUCKETS`.
+    pub buckets: [u64; 9],
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Recorder that keeps every metric in memory, so it can be inspected or
+/// rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct InMemoryRecorder {
+    counters: Mutex<BTreeMap<Key, u64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+}
+
+impl Recorder for InMemoryRecorder {
+    fn increment_counter(&self, name: &'static str, labels: Labels) {
+        *self.counters.lock().unwrap().entry(key(name, labels)).or_insert(0) += 1;
+    }
+
+    fn record_histogram(&self, name: &'static str, labels: Labels, value: f64) {
+        self.histograms.lock().unwrap().entry(key(name, labels)).or_default().observe(value);
+    }
+}
+
+impl InMemoryRecorder {
+    pub fn counter(&self, name: &'static str, labels: Labels) -> u64 {
+        self.counters.lock().unwrap().get(&key(name, labels)).copied().unwrap_or(0)
+    }
+
+    pub fn histogram(&self, name: &'static str, labels: Labels) -> Option<Histogram> {
+        self.histograms.lock().unwrap().get(&key(name, labels)).cloned()
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let mut last_name = "";
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if *name != last_name {
+                out.push_str(&format!("# TYPE {} counter\n", name));
+                last_name = name;
+            }
+            out.push_str(&format!("{}{} {}\n", name, render_labels(labels, None), value));
+        }
+
+        let mut last_name = "";
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if *name != last_name {
+                out.push_str(&format!("# TYPE {} histogram\n", name));
+                last_name = name;
+            }
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                let le = bound.to_string();
+                out.push_str(&format!("{}_bucket{} {}\n", name, render_labels(labels, Some(&le)), count));
+            }
+            out.push_str(&format!("{}_bucket{} {}\n", name, render_labels(labels, Some("+Inf")), histogram.count));
+            out.push_str(&format!("{}_sum{} {}\n", name, render_labels(labels, None), histogram.sum));
+            out.push_str(&format!("{}_count{} {}\n", name, render_labels(labels, None), histogram.count));
+        }
+        out
+    }
+}
+
+fn render_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut rendered: Vec<String> = labels.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    if let Some(le) = le {
+        rendered.push(format!("le=\"{}\"", le));
+    }
+
+    if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    }
+}
+
+/// Serve the metrics of `recorder` in the Prometheus text format on `addr`.
+/// Every request is answered with the metrics, whatever the path. Every
+/// connection gets its own thread, so a slow one doesn't hold up the others.
+pub fn serve_prometheus<A: ToSocketAddrs>(addr: A, recorder: Arc<InMemoryRecorder>)
+        -> std::io::Result<(std::net::SocketAddr, thread::JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let recorder = recorder.clone();
+                    thread::spawn(move || answer_metrics(stream, &recorder));
+                },
+                Err(e) => tracing::warn!(error = %e, "accept metrics connection"),
+            }
+        }
+    });
+    Ok((local_addr, handle))
+}
+
+fn answer_metrics(mut stream: TcpStream, recorder: &InMemoryRecorder) {
+    // Skip the request, it doesn't matter what was asked for. A client that
+    // never finishes its request still gets an answer after the timeout.
+    if let Err(e) = stream.set_read_timeout(Some(REQUEST_TIMEOUT)) {
+        tracing::warn!(error = %e, "set metrics read timeout");
+    }
+    {
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).map(|n| n > 0).unwrap_or(false) && line.trim() != "" {
+            line.clear();
+        }
+    }
+
+    let body = recorder.render_prometheus();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body);
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        tracing::warn!(error = %e, "write metrics response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn counters_are_kept_per_label_set() {
+        let recorder = InMemoryRecorder::default();
+        recorder.increment_counter(MESSAGES_TOTAL, &[("type", "State"), ("from", "Server")]);
+        recorder.increment_counter(MESSAGES_TOTAL, &[("from", "Server"), ("type", "State")]);
+        recorder.increment_counter(MESSAGES_TOTAL, &[("type", "Action"), ("from", "Bot")]);
+
+        assert_eq!(recorder.counter(MESSAGES_TOTAL, &[("type", "State"), ("from", "Server")]), 2);
+        assert_eq!(recorder.counter(MESSAGES_TOTAL, &[("type", "Action"), ("from", "Bot")]), 1);
+        assert_eq!(recorder.counter(MESSAGES_TOTAL, &[("type", "Error"), ("from", "Bot")]), 0);
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let recorder = InMemoryRecorder::default();
+        recorder.record_histogram(STATE_TO_ACTION_SECONDS, &[], 0.002);
+        recorder.record_histogram(STATE_TO_ACTION_SECONDS, &[], 0.2);
+        recorder.record_histogram(STATE_TO_ACTION_SECONDS, &[], 3.0);
+
+        let histogram = recorder.histogram(STATE_TO_ACTION_SECONDS, &[]).unwrap();
+        assert_eq!(histogram.buckets, [0, 1, 1, 1, 1, 1, 2, 2, 2]);
+        assert_eq!(histogram.count, 3);
+    }
+
+    #[test]
+    fn render_prometheus_text_format() {
+        let recorder = InMemoryRecorder::default();
+        recorder.increment_counter(HANDLE_ERRORS_TOTAL, &[("error", "UndefinedOutput")]);
+        recorder.record_histogram(STATE_TO_ACTION_SECONDS, &[], 0.5);
+
+        let text = recorder.render_prometheus();
+        assert!(text.contains("# TYPE wartemis_handle_errors_total counter\n"));
+        assert!(text.contains("wartemis_handle_errors_total{error=\"UndefinedOutput\"} 1\n"));
+        assert!(text.contains("# TYPE wartemis_state_to_action_seconds histogram\n"));
+        assert!(text.contains("wartemis_state_to_action_seconds_bucket{le=\"0.25\"} 0\n"));
+        assert!(text.contains("wartemis_state_to_action_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("wartemis_state_to_action_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("wartemis_state_to_action_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn serve_prometheus_answers_with_metrics() {
+        let recorder = Arc::new(InMemoryRecorder::default());
+        recorder.increment_counter(MESSAGES_TOTAL, &[("type", "State"), ("from", "Server")]);
+        let (addr, _) = serve_prometheus("127.0.0.1:0", recorder).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("wartemis_messages_total{from=\"Server\",type=\"State\"} 1\n"));
+    }
+
+    #[test]
+    fn silent_connection_does_not_block_others() {
+        let (addr, _) = serve_prometheus("127.0.0.1:0", Arc::new(InMemoryRecorder::default())).unwrap();
+        let _silent = TcpStream::connect(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
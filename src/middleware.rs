@@ -0,0 +1,468 @@
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::channel;
+use crate::handler::{ClientConfig, HandleError, Handler, Outputs, Response};
+use crate::message as msg;
+
+/// One layer of a `Middleware` stack. Both hooks get a message and return
+/// the messages to pass on: nothing drops it, several inject new ones.
+pub trait Layer: Send {
+    /// A message that came in, before the handler routes it.
+    fn incoming(&self, json: String) -> Vec<String> {
+        vec![json]
+    }
+
+    /// A message the handler routed to `output`, before it is sent.
+    fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+        vec![(output, json)]
+    }
+
+    /// Called on every client tick, returns messages to send. They pass the
+    /// outer layers like any outgoing message.
+    fn tick(&self) -> Vec<(Outputs, String)> {
+        Vec::new()
+    }
+
+    /// How soon the layer needs a tick, like when it holds messages back.
+    fn next_tick(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Wraps a handler in a stack of layers. The first layer added is the
+/// outermost one: it sees incoming messages first and outgoing messages
+/// last.
+pub struct Middleware<S = msg::MessageContent, A = msg::MessageContent> {
+    inner: Box<dyn Handler<S, A> + Send>,
+    layers: Vec<Box<dyn Layer>>,
+    // What the inner handler sends, and where it goes after the layers. In
+    // the order the outputs were added, so flushing is deterministic.
+    outputs: Vec<(Outputs, Receiver<String>, channel::OutputSender)>,
+}
+
+impl<S, A> Middleware<S, A> {
+    pub fn new(inner: Box<dyn Handler<S, A> + Send>) -> Self {
+        Middleware{
+            inner,
+            layers: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Add a layer inside the ones added before.
+    pub fn layer<L: Layer + 'static>(mut self, layer: L) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Pass what the inner handler sent through the layers, innermost first.
+    /// A message that can't be sent doesn't stop the others, the first error
+    /// is returned.
+    fn flush(&self) -> Result<(), HandleError> {
+        let mut result = Ok(());
+        for (output, routed, _) in &self.outputs {
+            for json in routed.try_iter() {
+                result = result.and(self.send_outgoing(self.layers.len(), vec![(output.clone(), json)]));
+            }
+        }
+        result
+    }
+
+    /// Send messages through the layers outside of `layers[depth]`.
+    fn send_outgoing(&self, depth: usize, mut messages: Vec<(Outputs, String)>) -> Result<(), HandleError> {
+        for layer in self.layers[..depth].iter().rev() {
+            messages = messages.into_iter()
+                .flat_map(|(output, json)| layer.outgoing(output, json))
+                .collect();
+        }
+        messages.into_iter()
+            .map(|(output, json)| self.send(json, output))
+            .fold(Ok(()), Result::and)
+    }
+
+    fn send(&self, json: String, output: Outputs) -> Result<(), HandleError> {
+        let (_, _, sender) = self.outputs.iter().find(|(added, _, _)| *added == output)
+            .ok_or_else(|| HandleError::UndefinedOutput(output.clone()))?;
+        sender.send(json).map_err(|e| HandleError::SendError{ source: e, output })
+    }
+}
+
+impl<S: DeserializeOwned, A: DeserializeOwned> Handler<S, A> for Middleware<S, A> {
+    fn handle(&self, json: String, message: msg::Message<S, A>) -> Result<Response, HandleError> {
+        let original = json.clone();
+        let mut messages = vec![json];
+        for layer in &self.layers {
+            messages = messages.into_iter().flat_map(|json| layer.incoming(json)).collect();
+        }
+
+        // A message that fails doesn't stop the others, the first error is
+        // returned once all are handled.
+        let mut message = Some(message);
+        let mut response = Ok(Response::Empty);
+        for json in messages {
+            // Only messages a layer rewrote or injected are parsed again.
+            let message = match message.take() {
+                Some(message) if json == original => message,
+                _ => match msg::deserialize_typed_message(&json) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        response = response.and(Err(e.into()));
+                        continue;
+                    },
+                },
+            };
+            let result = self.inner.handle(json, message);
+            let flushed = self.flush();
+            response = match (response, result) {
+                (Err(e), _) | (Ok(_), Err(e)) => Err(e),
+                (Ok(_), Ok(Response::SetID(id))) => Ok(Response::SetID(id)),
+                (Ok(_), Ok(Response::Action)) => Ok(Response::Action),
+                (Ok(kept), Ok(Response::Empty)) => Ok(kept),
+            };
+            response = flushed.and(response);
+        }
+        response
+    }
+
+    fn tick(&self) -> Result<Response, HandleError> {
+        let response = self.inner.tick();
+        self.flush()?;
+        for (depth, layer) in self.layers.iter().enumerate() {
+            self.send_outgoing(depth, layer.tick())?;
+        }
+        response
+    }
+
+    fn next_tick(&self) -> Option<Duration> {
+        self.layers.iter()
+            .filter_map(|layer| layer.next_tick())
+            .chain(self.inner.next_tick())
+            .min()
+    }
+
+    /// Injected messages pass every layer.
+    fn inject(&self, json: String, output: Outputs) -> Result<Response, HandleError> {
+        self.send_outgoing(self.layers.len(), vec![(output, json)])?;
+        Ok(Response::Empty)
+    }
+
+    fn add_output(&mut self, output_type: Outputs, output: channel::OutputSender) {
+        let (sender, routed) = crossbeam_channel::unbounded();
+        self.inner.add_output(output_type.clone(), sender.into());
+        self.outputs.retain(|(added, _, _)| *added != output_type);
+        self.outputs.push((output_type, routed, output));
+    }
+
+    fn client_config(&self) -> Option<&ClientConfig> {
+        self.inner.client_config()
+    }
+}
+
+/// `type` of a JSON message, if it has one.
+pub fn message_type(json: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    value.get("type")?.as_str().map(str::to_string)
+}
+
+/// Logs the type and size of every message passing the layer. The content
+/// is left out, a `Register` carries the token.
+pub struct Logging;
+
+impl Layer for Logging {
+    fn incoming(&self, json: String) -> Vec<String> {
+        tracing::debug!(msg_type = ?message_type(&json), size = json.len(), "incoming");
+        vec![json]
+    }
+
+    fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+        tracing::debug!(?output, msg_type = ?message_type(&json), size = json.len(), "outgoing");
+        vec![(output, json)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::MessageHandler;
+    use std::sync::{Arc, Mutex};
+
+    fn config() -> ClientConfig {
+        ClientConfig::builder().game("test_game").name("test_bot").build().unwrap()
+    }
+
+    struct Routed {
+        server: Receiver<String>,
+        bot: Receiver<String>,
+    }
+
+    fn middleware(layers: Vec<Box<dyn Layer>>) -> (Middleware, Routed) {
+        let mut middleware = Middleware::new(Box::new(MessageHandler::new(config())));
+        middleware.layers = layers;
+        let (server, server_rec) = crossbeam_channel::unbounded();
+        let (bot, bot_rec) = crossbeam_channel::unbounded();
+        middleware.add_output_channel(Outputs::Server, server);
+        middleware.add_output_channel(Outputs::Bot, bot);
+        (middleware, Routed{ server: server_rec, bot: bot_rec })
+    }
+
+    fn handle(middleware: &Middleware, json: &str) -> Result<Response, HandleError> {
+        middleware.handle(json.to_string(), msg::deserialize_message(json).unwrap())
+    }
+
+    /// Records the order in which layers see messages.
+    struct Trace {
+        name: &'static str,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Layer for Trace {
+        fn incoming(&self, json: String) -> Vec<String> {
+            self.seen.lock().unwrap().push(format!("{} in", self.name));
+            vec![json]
+        }
+
+        fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+            self.seen.lock().unwrap().push(format!("{} out", self.name));
+            vec![(output, json)]
+        }
+    }
+
+    #[cfg(test)]
+    mod chain {
+        use super::*;
+
+        #[test]
+        fn without_layers_messages_are_routed_as_usual() {
+            let (middleware, outputs) = middleware(Vec::new());
+            handle(&middleware, r#"{"type":"State","turn":1}"#).unwrap();
+            assert_eq!(outputs.bot.try_recv().unwrap(), r#"{"type":"State","turn":1}"#);
+
+            let response = handle(&middleware, r#"{"type":"RegisterSuccess","id":4}"#).unwrap();
+            assert!(matches!(response, Response::SetID(4)));
+        }
+
+        #[test]
+        fn first_layer_is_outermost() {
+            let seen = Arc::new(Mutex::new(Vec::new()));
+            let mut middleware = Middleware::new(Box::new(MessageHandler::new(config())))
+                .layer(Trace{ name: "outer", seen: seen.clone() })
+                .layer(Trace{ name: "inner", seen: seen.clone() });
+            let (server, server_rec) = crossbeam_channel::unbounded();
+            middleware.add_output_channel(Outputs::Server, server);
+            handle(&middleware, r#"{"type":"Action"}"#).unwrap();
+
+            assert!(server_rec.try_recv().is_ok());
+            assert_eq!(*seen.lock().unwrap(), vec!["outer in", "inner in", "inner out", "outer out"]);
+        }
+
+        #[test]
+        fn incoming_messages_can_be_modified() {
+            struct Rename;
+
+            impl Layer for Rename {
+                fn incoming(&self, json: String) -> Vec<String> {
+                    vec![json.replace("\"State\"", "\"GameEnd\"")]
+                }
+            }
+
+            let (middleware, outputs) = middleware(vec![Box::new(Rename)]);
+            handle(&middleware, r#"{"type":"State"}"#).unwrap();
+            assert_eq!(outputs.bot.try_recv().unwrap(), r#"{"type":"GameEnd"}"#);
+        }
+
+        #[test]
+        fn incoming_messages_can_be_dropped_or_injected() {
+            struct DropStateDoubleError;
+
+            impl Layer for DropStateDoubleError {
+                fn incoming(&self, json: String) -> Vec<String> {
+                    match message_type(&json).as_deref() {
+                        Some("State") => Vec::new(),
+                        Some("Error") => vec![json.clone(), json],
+                        _ => vec![json],
+                    }
+                }
+            }
+
+            let (middleware, outputs) = middleware(vec![Box::new(DropStateDoubleError)]);
+            handle(&middleware, r#"{"type":"State"}"#).unwrap();
+            handle(&middleware, r#"{"type":"Error"}"#).unwrap();
+            assert_eq!(outputs.bot.try_iter().collect::<Vec<_>>(), vec![r#"{"type":"Error"}"#; 2]);
+        }
+
+        #[test]
+        fn outgoing_messages_can_be_redirected() {
+            struct Mirror;
+
+            impl Layer for Mirror {
+                fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+                    vec![(Outputs::Bot, json.clone()), (output, json)]
+                }
+            }
+
+            let (middleware, outputs) = middleware(vec![Box::new(Mirror)]);
+            handle(&middleware, r#"{"type":"Action","x":1}"#).unwrap();
+            assert_eq!(outputs.server.try_recv().unwrap(), r#"{"type":"Action","x":1}"#);
+            assert_eq!(outputs.bot.try_recv().unwrap(), r#"{"type":"Action","x":1}"#);
+        }
+
+        #[test]
+        fn ticks_pass_the_outer_layers() {
+            struct Ticking;
+
+            impl Layer for Ticking {
+                fn tick(&self) -> Vec<(Outputs, String)> {
+                    vec![(Outputs::Server, r#"{"type":"Action"}"#.to_string())]
+                }
+            }
+
+            let seen = Arc::new(Mutex::new(Vec::new()));
+            let (middleware, outputs) = middleware(vec![
+                Box::new(Trace{ name: "outer", seen: seen.clone() }),
+                Box::new(Ticking),
+                Box::new(Trace{ name: "inner", seen: seen.clone() }),
+            ]);
+            middleware.tick().unwrap();
+
+            assert_eq!(outputs.server.try_recv().unwrap(), r#"{"type":"Action"}"#);
+            assert_eq!(*seen.lock().unwrap(), vec!["outer out"]);
+        }
+
+        #[test]
+        fn injected_message_to_missing_output_fails() {
+            struct ToViewer;
+
+            impl Layer for ToViewer {
+                fn outgoing(&self, _output: Outputs, json: String) -> Vec<(Outputs, String)> {
+                    vec![(Outputs::Viewer, json)]
+                }
+            }
+
+            let (middleware, _outputs) = middleware(vec![Box::new(ToViewer)]);
+            let result = handle(&middleware, r#"{"type":"State"}"#);
+            assert!(matches!(result, Err(HandleError::UndefinedOutput(Outputs::Viewer))));
+        }
+
+        #[test]
+        fn failing_message_does_not_stop_the_others() {
+            struct Surround;
+
+            impl Layer for Surround {
+                fn incoming(&self, json: String) -> Vec<String> {
+                    let register = r#"{"type":"Register","clientType":"bot","game":"g","name":"n"}"#;
+                    vec!["not json".to_string(), register.to_string(), json.clone(), json]
+                }
+            }
+
+            let (middleware, outputs) = middleware(vec![Box::new(Surround)]);
+            let result = handle(&middleware, r#"{"type":"State"}"#);
+            assert!(matches!(result, Err(HandleError::MessageError(_))));
+            assert_eq!(outputs.bot.try_iter().collect::<Vec<_>>(), vec![r#"{"type":"State"}"#; 2]);
+        }
+
+        #[test]
+        fn outputs_are_flushed_in_the_order_they_were_added() {
+            /// Sends every message to the server and the bot.
+            struct Both(Vec<(Outputs, channel::OutputSender)>);
+
+            impl Handler for Both {
+                fn handle(&self, json: String, _message: msg::Message) -> Result<Response, HandleError> {
+                    for (_, output) in &self.0 {
+                        output.send(json.clone()).unwrap();
+                    }
+                    Ok(Response::Empty)
+                }
+
+                fn add_output(&mut self, output_type: Outputs, output: channel::OutputSender) {
+                    self.0.push((output_type, output));
+                }
+            }
+
+            struct Order(Arc<Mutex<Vec<Outputs>>>);
+
+            impl Layer for Order {
+                fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+                    self.0.lock().unwrap().push(output.clone());
+                    vec![(output, json)]
+                }
+            }
+
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let mut middleware = Middleware::new(Box::new(Both(Vec::new()))).layer(Order(order.clone()));
+            let (bot, _bot_rec) = crossbeam_channel::unbounded();
+            let (server, _server_rec) = crossbeam_channel::unbounded();
+            middleware.add_output_channel(Outputs::Bot, bot);
+            middleware.add_output_channel(Outputs::Server, server);
+            for _ in 0..10 {
+                handle(&middleware, r#"{"type":"State"}"#).unwrap();
+            }
+            assert_eq!(*order.lock().unwrap(), vec![vec![Outputs::Bot, Outputs::Server]; 10].concat());
+        }
+
+        #[test]
+        fn rewritten_message_must_still_parse() {
+            struct Garble;
+
+            impl Layer for Garble {
+                fn incoming(&self, _json: String) -> Vec<String> {
+                    vec!["not json".to_string()]
+                }
+            }
+
+            let (middleware, _outputs) = middleware(vec![Box::new(Garble)]);
+            assert!(matches!(handle(&middleware, r#"{"type":"State"}"#), Err(HandleError::MessageError(_))));
+        }
+    }
+
+    #[cfg(test)]
+    mod logging {
+        use super::*;
+
+        #[test]
+        fn passes_messages_unchanged() {
+            assert_eq!(Logging.incoming("{}".to_string()), vec!["{}"]);
+            assert_eq!(Logging.outgoing(Outputs::Bot, "{}".to_string()), vec![(Outputs::Bot, "{}".to_string())]);
+        }
+
+        #[derive(Clone, Default)]
+        struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for LogBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn token_is_not_logged() {
+            let logs = LogBuffer::default();
+            let writer = logs.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(move || writer.clone())
+                .finish();
+
+            let config = ClientConfig::builder().game("test_game").name("test_bot")
+                .token(crate::secret::Secret::new("hunter2")).build().unwrap();
+            let mut middleware = Middleware::new(Box::new(MessageHandler::new(config))).layer(Logging);
+            let (server, server_rec) = crossbeam_channel::unbounded();
+            middleware.add_output_channel(Outputs::Server, server);
+            tracing::subscriber::with_default(subscriber, || {
+                handle(&middleware, r#"{"type":"Connected"}"#).unwrap();
+            });
+
+            assert!(server_rec.try_recv().unwrap().contains("hunter2"));
+            let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+            assert!(logs.contains(r#"msg_type=Some("Register")"#), "{}", logs);
+            assert!(!logs.contains("hunter2"), "{}", logs);
+        }
+    }
+}
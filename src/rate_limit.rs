@@ -0,0 +1,392 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::clock::{Clock, SystemClock};
+use crate::handler::Outputs;
+use crate::message as msg;
+use crate::middleware::{self, Layer};
+
+/// What happens to a server-bound message when its type is over the limit.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitPolicy {
+    /// Hold the message until the limit allows it, up to a maximum queue.
+    #[default]
+    Queue,
+    /// Throw the message away, the bot isn't told.
+    Drop,
+    /// Throw the message away and tell the bot with a local `Error`.
+    Error,
+}
+
+#[derive(Error,Debug,PartialEq)]
+#[error("limit for `{message_type}` should have per_second and burst above 0")]
+pub struct InvalidLimit {
+    pub message_type: String,
+}
+
+/// Token bucket: `burst` messages at once, refilled at `per_second`.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Limit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Limit {
+    pub fn is_valid(&self) -> bool {
+        self.per_second > 0.0 && self.per_second.is_finite() && self.burst > 0
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    queue: VecDeque<(String, String)>,
+}
+
+/// Middleware layer that limits the messages sent to the server, per
+/// message type. Types without a limit pass freely. Queued messages go out
+/// on a later message or on the tick asked for with `next_tick`.
+pub struct RateLimit {
+    limits: HashMap<String, Limit>,
+    policy: LimitPolicy,
+    max_queue: usize,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    pub fn new(policy: LimitPolicy) -> Self {
+        RateLimit{
+            limits: HashMap::new(),
+            policy,
+            max_queue: 16,
+            clock: Arc::new(SystemClock),
+            buckets: Mutex::new(Buckets{ buckets: HashMap::new(), queue: VecDeque::new() }),
+        }
+    }
+
+    pub fn limit(mut self, message_type: &str, limit: Limit) -> Result<Self, InvalidLimit> {
+        if !limit.is_valid() {
+            return Err(InvalidLimit{ message_type: message_type.to_string() });
+        }
+        self.limits.insert(message_type.to_string(), limit);
+        Ok(self)
+    }
+
+    /// Messages held back with `LimitPolicy::Queue` before new ones are
+    /// refused with an `Error`.
+    pub fn max_queue(mut self, max_queue: usize) -> Self {
+        self.max_queue = max_queue;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Take a token for `message_type`, if there is one.
+    fn take(&self, buckets: &mut Buckets, message_type: &str) -> bool {
+        let limit = match self.limits.get(message_type) {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let now = self.clock.now();
+        let bucket = buckets.buckets.entry(message_type.to_string())
+            .or_insert(Bucket{ tokens: limit.burst as f64, updated: now });
+        bucket.tokens = refilled(bucket, limit, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// How long until `message_type` has a token again.
+    fn wait(&self, buckets: &Buckets, message_type: &str) -> Duration {
+        let (limit, bucket) = match (self.limits.get(message_type), buckets.buckets.get(message_type)) {
+            (Some(limit), Some(bucket)) => (limit, bucket),
+            _ => return Duration::ZERO,
+        };
+        let missing = 1.0 - refilled(bucket, limit, self.clock.now());
+        Duration::from_secs_f64((missing / limit.per_second).max(0.0))
+    }
+
+    /// Queued messages whose limit allows them now, in order per type.
+    fn release(&self, buckets: &mut Buckets) -> Vec<(Outputs, String)> {
+        let mut released = Vec::new();
+        let mut blocked = HashSet::new();
+        let queue = std::mem::take(&mut buckets.queue);
+        for (message_type, json) in queue {
+            if !blocked.contains(&message_type) && self.take(buckets, &message_type) {
+                released.push((Outputs::Server, json));
+            } else {
+                blocked.insert(message_type.clone());
+                buckets.queue.push_back((message_type, json));
+            }
+        }
+        released
+    }
+}
+
+impl Layer for RateLimit {
+    fn outgoing(&self, output: Outputs, json: String) -> Vec<(Outputs, String)> {
+        if output != Outputs::Server {
+            return vec![(output, json)];
+        }
+        let message_type = match middleware::message_type(&json) {
+            Some(message_type) if self.limits.contains_key(&message_type) => message_type,
+            _ => return vec![(output, json)],
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut messages = self.release(&mut buckets);
+        let waiting = buckets.queue.iter().any(|(queued, _)| *queued == message_type);
+        if !waiting && self.take(&mut buckets, &message_type) {
+            messages.push((output, json));
+            return messages;
+        }
+
+        tracing::warn!(msg_type = %message_type, policy = ?self.policy, "rate limit exceeded");
+        match self.policy {
+            LimitPolicy::Queue if buckets.queue.len() < self.max_queue => buckets.queue.push_back((message_type, json)),
+            LimitPolicy::Queue | LimitPolicy::Error => messages.extend(limit_error(&message_type)),
+            LimitPolicy::Drop => (),
+        }
+        messages
+    }
+
+    fn tick(&self) -> Vec<(Outputs, String)> {
+        let mut buckets = self.buckets.lock().unwrap();
+        self.release(&mut buckets)
+    }
+
+    /// When the first queued message can go out.
+    fn next_tick(&self) -> Option<Duration> {
+        let buckets = self.buckets.lock().unwrap();
+        buckets.queue.iter()
+            .map(|(message_type, _)| self.wait(&buckets, message_type))
+            .min()
+    }
+}
+
+/// Tokens in `bucket` at `now`, at most the burst.
+fn refilled(bucket: &Bucket, limit: &Limit, now: Instant) -> f64 {
+    let refill = now.saturating_duration_since(bucket.updated).as_secs_f64() * limit.per_second;
+    (bucket.tokens + refill).min(limit.burst as f64)
+}
+
+/// Local `Error` for the bot about a message that was not sent.
+fn limit_error(message_type: &str) -> Option<(Outputs, String)> {
+    let error = msg::MessageContent{
+        content: json!({"message": format!("rate limit exceeded, {} not sent", message_type)}),
+    };
+    msg::serialize_message(msg::Message::Error(error)).ok()
+        .map(|json| (Outputs::Bot, json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::time::Duration;
+
+    const ACTION: &str = r#"{"type":"Action","n":0}"#;
+
+    fn action(n: u32) -> String {
+        format!(r#"{{"type":"Action","n":{}}}"#, n)
+    }
+
+    /// Two actions at once, then one per second.
+    fn rate_limit(policy: LimitPolicy) -> (RateLimit, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let layer = RateLimit::new(policy)
+            .limit("Action", Limit{ per_second: 1.0, burst: 2 }).unwrap()
+            .with_clock(clock.clone());
+        (layer, clock)
+    }
+
+    fn send(layer: &RateLimit, json: &str) -> Vec<(Outputs, String)> {
+        layer.outgoing(Outputs::Server, json.to_string())
+    }
+
+    fn is_error(messages: &[(Outputs, String)]) -> bool {
+        matches!(messages, [(Outputs::Bot, json)] if json.contains("rate limit exceeded, Action not sent"))
+    }
+
+    #[test]
+    fn burst_passes_then_tokens_refill() {
+        let (layer, clock) = rate_limit(LimitPolicy::Error);
+        assert_eq!(send(&layer, &action(1)), vec![(Outputs::Server, action(1))]);
+        assert_eq!(send(&layer, &action(2)), vec![(Outputs::Server, action(2))]);
+        assert!(is_error(&send(&layer, &action(3))));
+
+        clock.advance(Duration::from_millis(999));
+        assert!(is_error(&send(&layer, &action(4))));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(send(&layer, &action(5)), vec![(Outputs::Server, action(5))]);
+    }
+
+    #[test]
+    fn tokens_do_not_exceed_the_burst() {
+        let (layer, clock) = rate_limit(LimitPolicy::Error);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(send(&layer, ACTION).len(), 1);
+        assert_eq!(send(&layer, ACTION).len(), 1);
+        assert!(is_error(&send(&layer, ACTION)));
+    }
+
+    #[test]
+    fn limits_need_a_rate_and_a_burst() {
+        for limit in [Limit{ per_second: 1.0, burst: 0 }, Limit{ per_second: 0.0, burst: 1 },
+                Limit{ per_second: -1.0, burst: 1 }, Limit{ per_second: f64::NAN, burst: 1 }] {
+            let result = RateLimit::new(LimitPolicy::Queue).limit("Action", limit);
+            assert_eq!(result.err(), Some(InvalidLimit{ message_type: "Action".to_string() }));
+        }
+    }
+
+    #[test]
+    fn limits_are_per_message_type() {
+        let (layer, _clock) = rate_limit(LimitPolicy::Drop);
+        send(&layer, ACTION);
+        send(&layer, ACTION);
+        let register = r#"{"type":"Register"}"#;
+        assert_eq!(send(&layer, register), vec![(Outputs::Server, register.to_string())]);
+        assert_eq!(layer.outgoing(Outputs::Bot, ACTION.to_string()), vec![(Outputs::Bot, ACTION.to_string())]);
+    }
+
+    #[test]
+    fn error_policy_reports_to_the_bot() {
+        let (layer, _clock) = rate_limit(LimitPolicy::Error);
+        send(&layer, ACTION);
+        send(&layer, ACTION);
+        assert!(is_error(&send(&layer, ACTION)));
+    }
+
+    #[test]
+    fn drop_policy_drops_silently() {
+        let (layer, clock) = rate_limit(LimitPolicy::Drop);
+        send(&layer, ACTION);
+        send(&layer, ACTION);
+        assert!(send(&layer, &action(1)).is_empty());
+
+        // Nothing was queued either.
+        clock.advance(Duration::from_secs(1));
+        assert!(layer.tick().is_empty());
+        assert_eq!(layer.next_tick(), None);
+    }
+
+    #[test]
+    fn queue_policy_sends_later_in_order() {
+        let (layer, clock) = rate_limit(LimitPolicy::Queue);
+        send(&layer, &action(1));
+        send(&layer, &action(2));
+        assert!(send(&layer, &action(3)).is_empty());
+        assert!(send(&layer, &action(4)).is_empty());
+        assert!(layer.tick().is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.tick(), vec![(Outputs::Server, action(3))]);
+        clock.advance(Duration::from_secs(1));
+        // A new action waits behind the queued one.
+        assert_eq!(send(&layer, &action(5)), vec![(Outputs::Server, action(4))]);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(layer.tick(), vec![(Outputs::Server, action(5))]);
+    }
+
+    #[test]
+    fn next_tick_is_when_the_queue_can_move() {
+        let (layer, clock) = rate_limit(LimitPolicy::Queue);
+        send(&layer, ACTION);
+        assert_eq!(layer.next_tick(), None);
+        send(&layer, ACTION);
+        send(&layer, ACTION);
+        assert_eq!(layer.next_tick(), Some(Duration::from_secs(1)));
+
+        clock.advance(Duration::from_millis(750));
+        assert_eq!(layer.next_tick(), Some(Duration::from_millis(250)));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(layer.tick().len(), 1);
+        assert_eq!(layer.next_tick(), None);
+    }
+
+    #[test]
+    fn full_queue_reports_to_the_bot() {
+        let (layer, _clock) = rate_limit(LimitPolicy::Queue);
+        let layer = layer.max_queue(1);
+        send(&layer, ACTION);
+        send(&layer, ACTION);
+        assert!(send(&layer, ACTION).is_empty());
+        assert!(is_error(&send(&layer, ACTION)));
+    }
+
+    #[test]
+    fn limits_the_handler_output() {
+        use crate::handler::{ClientConfig, Handler, MessageHandler};
+        use crate::middleware::Middleware;
+
+        let (layer, clock) = rate_limit(LimitPolicy::Queue);
+        let config = ClientConfig::builder().game("test_game").name("test_bot").build().unwrap();
+        let mut middleware = Middleware::new(Box::new(MessageHandler::new(config))).layer(layer);
+        let (server, server_rec) = crossbeam_channel::unbounded();
+        let (bot, bot_rec) = crossbeam_channel::unbounded();
+        middleware.add_output_channel(Outputs::Server, server);
+        middleware.add_output_channel(Outputs::Bot, bot);
+
+        for _ in 0..3 {
+            middleware.handle(ACTION.to_string(), msg::deserialize_message(ACTION).unwrap()).unwrap();
+        }
+        assert_eq!(server_rec.try_iter().count(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        middleware.tick().unwrap();
+        assert_eq!(server_rec.try_iter().count(), 1);
+        assert!(bot_rec.try_recv().is_err());
+    }
+
+    #[test]
+    fn client_releases_the_queue_without_a_tick_interval() {
+        use crate::client::Client;
+        use crate::handler::{ClientConfig, Handler, MessageHandler};
+        use crate::middleware::Middleware;
+        use std::thread;
+
+        let (layer, clock) = rate_limit(LimitPolicy::Queue);
+        let (server, server_rec) = crossbeam_channel::unbounded();
+        let (bot_snd, bot_rec) = crossbeam_channel::unbounded();
+        let thread_clock = clock.clone();
+        thread::spawn(move || {
+            let config = ClientConfig::builder().game("test_game").name("test_bot").build().unwrap();
+            let mut middleware = Middleware::new(Box::new(MessageHandler::new(config))).layer(layer);
+            middleware.add_output_channel(Outputs::Server, server);
+            let mut client: Client = Client::new(Box::new(middleware), crossbeam_channel::never(), bot_rec);
+            client.set_clock(thread_clock);
+            client.start()
+        });
+
+        for _ in 0..3 {
+            bot_snd.send(ACTION.to_string()).unwrap();
+        }
+        assert_eq!(server_rec.recv().unwrap(), ACTION);
+        assert_eq!(server_rec.recv().unwrap(), ACTION);
+        loop {
+            if server_rec.try_recv().is_ok() {
+                break;
+            }
+            clock.advance(Duration::from_millis(100));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,589 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::RecvTimeoutError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::channel;
+use crate::clock::{Clock, Deadline, SystemClock};
+use crate::message as msg;
+use crate::rules::GameRules;
+use crate::transport::{self, Attached, Transport, TransportError};
+use crate::validate;
+
+#[derive(Error,Debug)]
+pub enum RefereeError {
+    #[error("player {player}: {source}")]
+    Transport{
+        player: usize,
+        source: TransportError,
+    },
+
+    #[error("player {player} disconnected")]
+    Disconnected{ player: usize },
+
+    #[error("player {player} did not register in time")]
+    RegisterTimeout{ player: usize },
+
+    #[error("player {player} registered for `{game}` instead of `{expected}`")]
+    WrongGame{
+        player: usize,
+        game: String,
+        expected: String,
+    },
+
+    #[error("replay differs from the game in turn {turn}")]
+    ReplayMismatch{ turn: usize },
+
+    #[error("player {player} has no valid id")]
+    TooManyPlayers{ player: usize },
+
+    #[error(transparent)]
+    Message(#[from] msg::MessageError),
+}
+
+impl RefereeError {
+    /// Player at fault, `None` when the error isn't caused by a player.
+    pub fn player(&self) -> Option<usize> {
+        match self {
+            RefereeError::Transport{ player, .. }
+            | RefereeError::Disconnected{ player }
+            | RefereeError::RegisterTimeout{ player }
+            | RefereeError::WrongGame{ player, .. } => Some(*player),
+            RefereeError::ReplayMismatch{ .. }
+            | RefereeError::TooManyPlayers{ .. }
+            | RefereeError::Message(_) => None,
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct RefereeConfig {
+    /// Game the players have to register for.
+    pub game: String,
+    pub register_timeout: Duration,
+    /// How long players get to answer a `State`.
+    pub action_timeout: Duration,
+    /// The game ends after this many turns, even when it isn't terminal.
+    pub max_turns: usize,
+    /// Seed for the game rules, a random one is picked when not set.
+    pub seed: Option<u64>,
+}
+
+impl Default for RefereeConfig {
+    fn default() -> Self {
+        RefereeConfig{
+            game: String::new(),
+            register_timeout: Duration::from_secs(5),
+            action_timeout: Duration::from_secs(1),
+            max_turns: 1000,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct MatchResult<S> {
+    /// Registered names, in the order of the connections.
+    pub players: Vec<String>,
+    pub turns: usize,
+    pub seed: u64,
+    /// Score per player, in the same order as `players`.
+    pub scores: Vec<f64>,
+    pub final_state: S,
+    pub replay: Replay,
+}
+
+/// Everything the players saw and did during a match.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Replay {
+    /// Seed the game was played with, see `reproduce`.
+    pub seed: u64,
+    pub players: Vec<String>,
+    pub turns: Vec<ReplayTurn>,
+    pub scores: Vec<f64>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct ReplayTurn {
+    /// The `State` message sent to every player.
+    pub states: Vec<Value>,
+    /// The accepted `Action` of every player, `None` when it had none.
+    pub actions: Vec<Option<Value>>,
+}
+
+/// Actions of one turn and their record in the replay.
+type Turn<A> = (Vec<Option<A>>, ReplayTurn);
+
+struct Player {
+    name: String,
+    connection: Attached,
+}
+
+/// Closes the connections when the match ends, however it ends. A match
+/// that finished waits for its last messages to be sent first.
+struct CloseOnDrop<'a>(&'a [Arc<dyn Transport>]);
+
+impl Drop for CloseOnDrop<'_> {
+    fn drop(&mut self) {
+        for connection in self.0 {
+            let _ = connection.close();
+        }
+    }
+}
+
+/// Runs a game between locally connected bots, speaking the same protocol
+/// as the Wartemis server.
+pub struct Referee<R> {
+    rules: R,
+    config: RefereeConfig,
+    clock: Arc<dyn Clock>,
+}
+
+impl<R: GameRules> Referee<R> {
+    pub fn new(rules: R, config: RefereeConfig) -> Self {
+        Referee{ rules, config, clock: Arc::new(SystemClock) }
+    }
+
+    /// Use `clock` for the register and action timeouts.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Play one match, every connection is a player.
+    pub fn run(&self, connections: Vec<Arc<dyn Transport>>) -> Result<MatchResult<R::State>, RefereeError> {
+        let _close = CloseOnDrop(&connections);
+        let mut players = Vec::new();
+        for (index, connection) in connections.iter().enumerate() {
+            connection.connect().map_err(|e| RefereeError::Transport{ player: index, source: e })?;
+            let connection = transport::attach(connection.clone(), &channel::ChannelConfig::default());
+            send(&connection, index, msg::Message::Connected(msg::Connected{}))?;
+            players.push(self.register(index, connection)?);
+        }
+        let names: Vec<String> = players.iter().map(|player| player.name.clone()).collect();
+        let seed = self.config.seed.unwrap_or_else(rand::random);
+        tracing::info!(players = ?names, seed, "match started");
+
+        let mut state = self.rules.initial_state(players.len(), seed);
+        let mut turns = 0;
+        let mut replay = Vec::new();
+        while turns < self.config.max_turns && !self.rules.is_terminal(&state) {
+            let (actions, turn) = self.play_turn(&players, &state)?;
+            self.rules.apply(&mut state, actions);
+            replay.push(turn);
+            turns += 1;
+        }
+
+        let scores = self.rules.scores(&state);
+        tracing::info!(turns, ?scores, "match ended");
+        for (index, player) in players.iter().enumerate() {
+            let result = msg::MessageContent{ content: json!({
+                "turns": turns,
+                "players": names,
+                "scores": scores,
+                "you": index,
+            })};
+            send(&player.connection, index, msg::Message::GameEnd(result))?;
+        }
+        // Let the results go out before the connections are closed.
+        for player in players {
+            drop(player.connection.outgoing);
+            let _ = player.connection.writer.join();
+        }
+
+        let replay = Replay{ seed, players: names.clone(), turns: replay, scores: scores.clone() };
+        Ok(MatchResult{ players: names, turns, seed, scores, final_state: state, replay })
+    }
+
+    fn register(&self, index: usize, connection: Attached) -> Result<Player, RefereeError> {
+        let deadline = Deadline::after(&*self.clock, self.config.register_timeout);
+        loop {
+            let json = match deadline.recv(&connection.incoming) {
+                Ok(json) => json,
+                Err(RecvTimeoutError::Timeout) => return Err(RefereeError::RegisterTimeout{ player: index }),
+                Err(RecvTimeoutError::Disconnected) => return Err(RefereeError::Disconnected{ player: index }),
+            };
+
+            let register = match msg::deserialize_message(&json) {
+                Ok(msg::Message::Register(register)) => register,
+                Ok(other) => {
+                    tracing::warn!(player = index, msg_type = other.type_name(), "expected Register");
+                    continue;
+                },
+                Err(e) => {
+                    tracing::warn!(player = index, error = %e, "dropping message");
+                    continue;
+                },
+            };
+
+            if !self.config.game.is_empty() && register.game != self.config.game {
+                let error = msg::MessageContent{ content: json!({"message": format!("unknown game `{}`", register.game)}) };
+                send(&connection, index, msg::Message::Error(error))?;
+                return Err(RefereeError::WrongGame{ player: index, game: register.game, expected: self.config.game.clone() });
+            }
+
+            let id = i32::try_from(index + 1).map_err(|_| RefereeError::TooManyPlayers{ player: index })?;
+            send(&connection, index, msg::Message::RegisterSuccess(msg::RegisterSuccess{ id }))?;
+            return Ok(Player{ name: register.name, connection });
+        }
+    }
+
+    /// Send every player its view of the state and collect their actions
+    /// until the action timeout runs out.
+    fn play_turn(&self, players: &[Player], state: &R::State) -> Result<Turn<R::Action>, RefereeError> {
+        let mut states = Vec::with_capacity(players.len());
+        for (index, player) in players.iter().enumerate() {
+            let view = self.rules.view(state, index);
+            let state_json = msg::serialize_typed_message(msg::Message::<R::View, msg::MessageContent>::State(view))?;
+            states.push(serde_json::from_str(&state_json).map_err(|e| msg::MessageError::Deserialize{ source: e })?);
+            // Late answers to the previous turn don't count for this one.
+            while player.connection.incoming.try_recv().is_ok() {}
+            send_json(&player.connection, index, state_json)?;
+        }
+
+        let deadline = Deadline::after(&*self.clock, self.config.action_timeout);
+        let (actions, recorded) = players.iter().enumerate()
+            .map(|(index, player)| match self.receive_action(index, player, state, &deadline) {
+                Some((action, json)) => (Some(action), serde_json::from_str(&json).ok()),
+                None => (None, None),
+            })
+            .unzip();
+        Ok((actions, ReplayTurn{ states, actions: recorded }))
+    }
+
+    /// The first valid action of the player, together with the message it
+    /// came in.
+    fn receive_action(&self, index: usize, player: &Player, state: &R::State, deadline: &Deadline) -> Option<(R::Action, String)> {
+        loop {
+            let json = match deadline.recv(&player.connection.incoming) {
+                Ok(json) => json,
+                Err(_) => {
+                    tracing::debug!(player = %player.name, "no action in time");
+                    return None;
+                },
+            };
+
+            let result = match msg::deserialize_typed_message::<msg::MessageContent, R::Action>(&json) {
+                Ok(msg::Message::Action(action)) => self.rules.validate(state, index, &action).map(|_| action),
+                Ok(other) => {
+                    tracing::debug!(player = %player.name, msg_type = other.type_name(), "ignoring message");
+                    continue;
+                },
+                Err(e) => Err(e.to_string()),
+            };
+
+            return match result {
+                Ok(action) => Some((action, json)),
+                Err(reason) => {
+                    tracing::debug!(player = %player.name, %reason, "invalid action");
+                    let _ = send(&player.connection, index, msg::Message::Error(validate::invalid_action_error(&reason)));
+                    None
+                },
+            };
+        }
+    }
+}
+
+/// Play a recorded match again without any players: start from the
+/// recorded seed and apply the recorded actions. Fails when a player would
+/// have seen a different state than the one in the replay.
+pub fn reproduce<R: GameRules>(rules: &R, replay: &Replay) -> Result<R::State, RefereeError> {
+    let mut state = rules.initial_state(replay.players.len(), replay.seed);
+    for (turn, recorded) in replay.turns.iter().enumerate() {
+        for (player, recorded_state) in recorded.states.iter().enumerate() {
+            let view = serde_json::to_value(msg::Message::<R::View, msg::MessageContent>::State(rules.view(&state, player)))
+                .map_err(|e| msg::MessageError::Serialize{ source: e })?;
+            if &view != recorded_state {
+                return Err(RefereeError::ReplayMismatch{ turn });
+            }
+        }
+
+        let mut actions = Vec::with_capacity(recorded.actions.len());
+        for action in &recorded.actions {
+            actions.push(match action {
+                Some(action) => match msg::deserialize_typed_message::<msg::MessageContent, R::Action>(&action.to_string())? {
+                    msg::Message::Action(action) => Some(action),
+                    _ => return Err(RefereeError::ReplayMismatch{ turn }),
+                },
+                None => None,
+            });
+        }
+        rules.apply(&mut state, actions);
+    }
+    Ok(state)
+}
+
+fn send(connection: &Attached, player: usize, message: msg::Message) -> Result<(), RefereeError> {
+    send_json(connection, player, msg::serialize_message(message)?)
+}
+
+fn send_json(connection: &Attached, player: usize, json: String) -> Result<(), RefereeError> {
+    connection.outgoing.send(json).map_err(|_| RefereeError::Disconnected{ player })
+}
+
+/// Start an in-process bot client and return the other end of its
+/// connection, for a referee or for a client to drive as its bot.
+#[cfg(test)]
+pub(crate) fn bot_connection<B>(bot: B, name: &str, game: &str) -> Arc<dyn Transport>
+        where B: crate::bot::Bot + Send + 'static, B::State: 'static, B::Action: 'static {
+    use crate::transport::memory::MemoryTransport;
+
+    let (referee_end, client_end) = MemoryTransport::pair();
+    let client_config = crate::handler::ClientConfig::builder().game(game).name(name).build().unwrap();
+
+    std::thread::spawn(move || {
+        let client_end: Arc<dyn Transport> = Arc::new(client_end);
+        client_end.connect().unwrap();
+        let server = transport::attach(client_end, &channel::ChannelConfig::default());
+        let client = crate::client::Client::with_bot(bot, client_config, server.incoming, server.outgoing);
+        let _ = client.start();
+    });
+    Arc::new(referee_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::transport::memory::MemoryTransport;
+    use serde::{Deserialize, Serialize};
+    use std::thread;
+
+    /// Players take turns adding to a shared total, the game ends at 10.
+    struct CountToTen;
+
+    #[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+    struct Count {
+        total: u32,
+        missed: u32,
+    }
+
+    #[derive(Serialize,Deserialize)]
+    struct Add {
+        add: u32,
+    }
+
+    impl GameRules for CountToTen {
+        type State = Count;
+        type View = Count;
+        type Action = Add;
+
+        fn initial_state(&self, _players: usize, _seed: u64) -> Count {
+            Count{ total: 0, missed: 0 }
+        }
+
+        fn view(&self, state: &Count, _player: usize) -> Count {
+            state.clone()
+        }
+
+        fn validate(&self, _state: &Count, _player: usize, action: &Add) -> Result<(), String> {
+            match action.add {
+                1..=3 => Ok(()),
+                add => Err(format!("can't add {}", add)),
+            }
+        }
+
+        fn apply(&self, state: &mut Count, actions: Vec<Option<Add>>) {
+            for action in actions {
+                match action {
+                    Some(action) => state.total += action.add,
+                    None => state.missed += 1,
+                }
+            }
+        }
+
+        fn is_terminal(&self, state: &Count) -> bool {
+            state.total >= 10
+        }
+
+        fn scores(&self, state: &Count) -> Vec<f64> {
+            vec![state.total as f64]
+        }
+    }
+
+    struct AddOne;
+
+    impl Bot for AddOne {
+        type State = Count;
+        type Action = Add;
+
+        fn on_state(&mut self, _state: &Count) -> Add {
+            Add{ add: 1 }
+        }
+    }
+
+    fn config() -> RefereeConfig {
+        RefereeConfig{
+            game: "count".to_string(),
+            register_timeout: Duration::from_secs(5),
+            action_timeout: Duration::from_secs(5),
+            max_turns: 100,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn two_bots_play_a_match() {
+        let referee = Referee::new(CountToTen, config());
+        let result = referee.run(vec![
+            bot_connection(AddOne, "first", "count"),
+            bot_connection(AddOne, "second", "count"),
+        ]).unwrap();
+
+        assert_eq!(result.players, vec!["first", "second"]);
+        assert_eq!(result.turns, 5);
+        assert_eq!(result.final_state, Count{ total: 10, missed: 0 });
+
+        assert_eq!(result.replay.seed, result.seed);
+        assert_eq!(reproduce(&CountToTen, &result.replay).unwrap(), result.final_state);
+
+        let last = result.replay.turns.last().unwrap();
+        assert_eq!(last.states[1], json!({"type": "State", "total": 8, "missed": 0}));
+        assert_eq!(last.actions[0], Some(json!({"type": "Action", "add": 1})));
+    }
+
+    #[test]
+    fn bots_receive_the_result() {
+        struct Reporting(crossbeam_channel::Sender<Value>);
+
+        impl Bot for Reporting {
+            type State = Count;
+            type Action = Add;
+
+            fn on_state(&mut self, _state: &Count) -> Add {
+                Add{ add: 3 }
+            }
+
+            fn on_game_end(&mut self, result: &msg::MessageContent) {
+                let _ = self.0.send(result.content.clone());
+            }
+        }
+
+        let (results, received) = crossbeam_channel::unbounded();
+        let referee = Referee::new(CountToTen, config());
+        referee.run(vec![
+            bot_connection(Reporting(results.clone()), "first", "count"),
+            bot_connection(Reporting(results), "second", "count"),
+        ]).unwrap();
+
+        let mut seen: Vec<Value> = received.iter().map(|result| result["you"].clone()).collect();
+        seen.sort_by_key(|you| you.as_u64());
+        assert_eq!(seen, vec![json!(0), json!(1)]);
+    }
+
+    #[test]
+    fn silent_player_misses_turns() {
+        let (silent, silent_end) = MemoryTransport::pair();
+        silent_end.connect().unwrap();
+        thread::spawn(move || {
+            // Register, then never answer a state.
+            while let Ok(Some(json)) = silent_end.receive() {
+                if json.contains("Connected") {
+                    silent_end.send(r#"{"type":"Register","clientType":"bot","game":"count","name":"silent"}"#).unwrap();
+                }
+            }
+        });
+
+        let referee = Referee::new(CountToTen, RefereeConfig{ action_timeout: Duration::from_millis(20), ..config() });
+        let result = referee.run(vec![bot_connection(AddOne, "first", "count"), Arc::new(silent)]).unwrap();
+
+        assert_eq!(result.turns, 10);
+        assert_eq!(result.final_state.missed, 10);
+        assert!(result.replay.turns.iter().all(|turn| turn.actions[1].is_none()));
+    }
+
+    #[test]
+    fn action_timeout_follows_the_clock() {
+        let (silent, silent_end) = MemoryTransport::pair();
+        silent_end.connect().unwrap();
+        thread::spawn(move || {
+            while let Ok(Some(json)) = silent_end.receive() {
+                if json.contains("Connected") {
+                    silent_end.send(r#"{"type":"Register","clientType":"bot","game":"count","name":"silent"}"#).unwrap();
+                }
+            }
+        });
+
+        // A turn would take an hour on the wall clock.
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let referee = Referee::new(CountToTen, RefereeConfig{
+            register_timeout: Duration::from_secs(1000 * 3600),
+            action_timeout: Duration::from_secs(3600),
+            max_turns: 3,
+            ..config()
+        }).with_clock(clock.clone());
+        let (done, result) = crossbeam_channel::bounded(1);
+        thread::spawn(move || done.send(referee.run(vec![Arc::new(silent)])).unwrap());
+
+        let result = loop {
+            clock.advance(Duration::from_secs(3600));
+            if let Ok(result) = result.try_recv() {
+                break result.unwrap();
+            }
+            thread::yield_now();
+        };
+        assert_eq!(result.final_state.missed, 3);
+    }
+
+    #[test]
+    fn invalid_action_is_reported_and_missed() {
+        struct AddTen;
+
+        impl Bot for AddTen {
+            type State = Count;
+            type Action = Add;
+
+            fn on_state(&mut self, _state: &Count) -> Add {
+                Add{ add: 10 }
+            }
+        }
+
+        let referee = Referee::new(CountToTen, RefereeConfig{ max_turns: 2, ..config() });
+        let result = referee.run(vec![bot_connection(AddTen, "greedy", "count")]).unwrap();
+        assert_eq!(result.final_state, Count{ total: 0, missed: 2 });
+    }
+
+    #[test]
+    fn seed_is_passed_to_the_rules() {
+        let referee = Referee::new(CountToTen, RefereeConfig{ seed: Some(42), max_turns: 1, ..config() });
+        let result = referee.run(vec![bot_connection(AddOne, "only", "count")]).unwrap();
+        assert_eq!(result.seed, 42);
+        assert_eq!(result.replay.seed, 42);
+    }
+
+    #[test]
+    fn tampered_replay_is_detected() {
+        let referee = Referee::new(CountToTen, config());
+        let mut replay = referee.run(vec![bot_connection(AddOne, "only", "count")]).unwrap().replay;
+        replay.turns[2].actions[0] = Some(json!({"type": "Action", "add": 3}));
+        assert!(matches!(reproduce(&CountToTen, &replay), Err(RefereeError::ReplayMismatch{ turn: 3 })));
+    }
+
+    #[test]
+    fn max_turns_ends_the_game() {
+        let referee = Referee::new(CountToTen, RefereeConfig{ max_turns: 3, ..config() });
+        let result = referee.run(vec![bot_connection(AddOne, "only", "count")]).unwrap();
+        assert_eq!(result.turns, 3);
+    }
+
+    #[test]
+    fn wrong_game_is_refused() {
+        let referee = Referee::new(CountToTen, config());
+        let result = referee.run(vec![bot_connection(AddOne, "lost", "chess")]);
+        assert!(matches!(result, Err(RefereeError::WrongGame{ player: 0, .. })));
+    }
+
+    #[test]
+    fn failed_match_closes_every_connection() {
+        let registered = bot_connection(AddOne, "first", "count");
+        let referee = Referee::new(CountToTen, config());
+        let result = referee.run(vec![registered.clone(), bot_connection(AddOne, "lost", "chess")]);
+
+        assert!(matches!(result, Err(RefereeError::WrongGame{ player: 1, .. })));
+        assert_eq!(registered.health(), transport::Health::Closed);
+    }
+}
@@ -0,0 +1,36 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+pub mod goofspiel;
+
+/// The rules of a game the referee can run.
+pub trait GameRules {
+    /// Full game state, only the referee sees it.
+    type State;
+    /// What one player gets to see of the state, must serialize to a JSON
+    /// object.
+    type View: Serialize;
+    type Action: DeserializeOwned;
+
+    /// Any randomness of the game has to come from `seed`, so a match can
+    /// be reproduced from its replay.
+    fn initial_state(&self, players: usize, seed: u64) -> Self::State;
+
+    /// The `State` message sent to `player`.
+    fn view(&self, state: &Self::State, player: usize) -> Self::View;
+
+    /// Checks an action before it is played. Rejected actions are reported
+    /// to the player and count as no action.
+    fn validate(&self, _state: &Self::State, _player: usize, _action: &Self::Action) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Play one turn. `actions[i]` is `None` when player `i` didn't send a
+    /// valid action in time.
+    fn apply(&self, state: &mut Self::State, actions: Vec<Option<Self::Action>>);
+
+    fn is_terminal(&self, state: &Self::State) -> bool;
+
+    /// Score per player, higher is better.
+    fn scores(&self, state: &Self::State) -> Vec<f64>;
+}
@@ -0,0 +1,243 @@
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::GameRules;
+
+/// Goofspiel: every player holds the cards `1..=cards`. Each turn a prize
+/// card is revealed and everyone bids one card from their hand, the single
+/// highest bid wins the prize. Ties win nothing.
+#[derive(Debug,Clone)]
+pub struct Goofspiel {
+    cards: u32,
+    shuffle: bool,
+}
+
+impl Goofspiel {
+    /// Prizes come in order, lowest first.
+    pub fn new(cards: u32) -> Self {
+        Goofspiel{ cards, shuffle: false }
+    }
+
+    /// Shuffle the prizes with the seed of the match.
+    pub fn shuffled(mut self) -> Self {
+        self.shuffle = true;
+        self
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct State {
+    /// Prizes still to come, the first one is up for bidding.
+    pub prizes: Vec<u32>,
+    pub hands: Vec<Vec<u32>>,
+    pub scores: Vec<u32>,
+    pub last_bids: Vec<u32>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct View {
+    pub prize: u32,
+    pub hand: Vec<u32>,
+    /// Scores of every player, yours is at `you`.
+    pub scores: Vec<u32>,
+    pub you: usize,
+    /// Everyone's bid in the previous turn.
+    pub last_bids: Vec<u32>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Bid {
+    pub card: u32,
+}
+
+impl GameRules for Goofspiel {
+    type State = State;
+    type View = View;
+    type Action = Bid;
+
+    fn initial_state(&self, players: usize, seed: u64) -> State {
+        let mut prizes: Vec<u32> = (1..=self.cards).collect();
+        if self.shuffle {
+            prizes.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        }
+        State{
+            prizes,
+            hands: vec![(1..=self.cards).collect(); players],
+            scores: vec![0; players],
+            last_bids: Vec::new(),
+        }
+    }
+
+    fn view(&self, state: &State, player: usize) -> View {
+        View{
+            prize: state.prizes.first().copied().unwrap_or(0),
+            hand: state.hands[player].clone(),
+            scores: state.scores.clone(),
+            you: player,
+            last_bids: state.last_bids.clone(),
+        }
+    }
+
+    fn validate(&self, state: &State, player: usize, bid: &Bid) -> Result<(), String> {
+        if state.hands[player].contains(&bid.card) {
+            Ok(())
+        } else {
+            Err(format!("card {} is not in your hand", bid.card))
+        }
+    }
+
+    /// A player without a bid plays their lowest card.
+    fn apply(&self, state: &mut State, bids: Vec<Option<Bid>>) {
+        if state.prizes.is_empty() {
+            return;
+        }
+        let prize = state.prizes.remove(0);
+
+        let mut played = Vec::with_capacity(bids.len());
+        for (hand, bid) in state.hands.iter_mut().zip(bids) {
+            let card = bid.map(|bid| bid.card)
+                .filter(|card| hand.contains(card))
+                .or_else(|| hand.iter().min().copied())
+                .unwrap_or(0);
+            hand.retain(|&held| held != card);
+            played.push(card);
+        }
+
+        let highest = played.iter().max().copied().unwrap_or(0);
+        let mut winners = played.iter().enumerate().filter(|(_, &card)| card == highest);
+        if let (Some((winner, _)), None) = (winners.next(), winners.next()) {
+            state.scores[winner] += prize;
+        }
+        state.last_bids = played;
+    }
+
+    fn is_terminal(&self, state: &State) -> bool {
+        state.prizes.is_empty()
+    }
+
+    fn scores(&self, state: &State) -> Vec<f64> {
+        state.scores.iter().map(|&score| score as f64).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::referee::{bot_connection, Referee, RefereeConfig};
+    use std::time::Duration;
+
+    fn bid(card: u32) -> Option<Bid> {
+        Some(Bid{ card })
+    }
+
+    #[test]
+    fn highest_bid_wins_the_prize() {
+        let rules = Goofspiel::new(3);
+        let mut state = rules.initial_state(2, 0);
+        rules.apply(&mut state, vec![bid(3), bid(1)]);
+
+        assert_eq!(state.scores, vec![1, 0]);
+        assert_eq!(state.hands, vec![vec![1, 2], vec![2, 3]]);
+        assert_eq!(state.last_bids, vec![3, 1]);
+    }
+
+    #[test]
+    fn tie_wins_nothing() {
+        let rules = Goofspiel::new(3);
+        let mut state = rules.initial_state(2, 0);
+        rules.apply(&mut state, vec![bid(2), bid(2)]);
+        assert_eq!(state.scores, vec![0, 0]);
+        assert_eq!(state.prizes, vec![2, 3]);
+    }
+
+    #[test]
+    fn missing_bid_plays_the_lowest_card() {
+        let rules = Goofspiel::new(3);
+        let mut state = rules.initial_state(2, 0);
+        rules.apply(&mut state, vec![None, bid(2)]);
+        assert_eq!(state.last_bids, vec![1, 2]);
+        assert_eq!(state.scores, vec![0, 1]);
+    }
+
+    #[test]
+    fn only_cards_in_hand_can_be_bid() {
+        let rules = Goofspiel::new(3);
+        let mut state = rules.initial_state(1, 0);
+        assert!(rules.validate(&state, 0, &Bid{ card: 3 }).is_ok());
+        rules.apply(&mut state, vec![bid(3)]);
+        assert!(rules.validate(&state, 0, &Bid{ card: 3 }).is_err());
+    }
+
+    #[test]
+    fn view_shows_only_the_own_hand() {
+        let rules = Goofspiel::new(2);
+        let mut state = rules.initial_state(2, 0);
+        rules.apply(&mut state, vec![bid(1), bid(2)]);
+
+        let view = rules.view(&state, 1);
+        assert_eq!(view, View{ prize: 2, hand: vec![1], scores: vec![0, 1], you: 1, last_bids: vec![1, 2] });
+        assert_eq!(serde_json::to_value(&view).unwrap()["lastBids"], serde_json::json!([1, 2]));
+    }
+
+    #[test]
+    fn shuffled_prizes_follow_the_seed() {
+        let rules = Goofspiel::new(13).shuffled();
+        let prizes = rules.initial_state(2, 7).prizes;
+        assert_eq!(rules.initial_state(2, 7).prizes, prizes);
+        assert_ne!(rules.initial_state(2, 8).prizes, prizes);
+
+        let mut sorted = prizes.clone();
+        sorted.sort();
+        assert_eq!(sorted, (1..=13).collect::<Vec<u32>>());
+    }
+
+    /// Bids the prize itself, or its lowest card when that's gone.
+    struct MatchPrize;
+
+    impl Bot for MatchPrize {
+        type State = View;
+        type Action = Bid;
+
+        fn on_state(&mut self, view: &View) -> Bid {
+            let card = if view.hand.contains(&view.prize) { view.prize } else { view.hand[0] };
+            Bid{ card }
+        }
+    }
+
+    /// Always bids its highest card.
+    struct Highest;
+
+    impl Bot for Highest {
+        type State = View;
+        type Action = Bid;
+
+        fn on_state(&mut self, view: &View) -> Bid {
+            Bid{ card: *view.hand.iter().max().unwrap() }
+        }
+    }
+
+    #[test]
+    fn referee_runs_a_full_match() {
+        let config = RefereeConfig{
+            game: "goofspiel".to_string(),
+            action_timeout: Duration::from_secs(5),
+            ..RefereeConfig::default()
+        };
+        let referee = Referee::new(Goofspiel::new(5), config);
+        let result = referee.run(vec![
+            bot_connection(MatchPrize, "matcher", "goofspiel"),
+            bot_connection(Highest, "highest", "goofspiel"),
+        ]).unwrap();
+
+        // Prizes 1..=5 against bids 1..=5 and 5..=1: the high bidder takes 1
+        // and 2, 3 is a tie, the matcher takes 4 and 5.
+        assert_eq!(result.players, vec!["matcher", "highest"]);
+        assert_eq!(result.turns, 5);
+        assert_eq!(result.scores, vec![9.0, 3.0]);
+        assert!(result.final_state.hands.iter().all(|hand| hand.is_empty()));
+    }
+}
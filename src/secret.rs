@@ -0,0 +1,120 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(Error,Debug)]
+pub enum SecretError {
+    #[error("read secret from `{path}`: {source}")]
+    File{
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("read secret from environment variable `{0}`")]
+    Env(String),
+
+    #[error("secret from {0} is empty")]
+    Empty(String),
+}
+
+/// A credential that is sent to the server but never shows up in `Debug` or
+/// `Display` output, and therefore never in logs either.
+#[derive(Clone,PartialEq,Eq,Serialize,Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Secret(secret.into())
+    }
+
+    /// Read a secret from a file, surrounding whitespace is ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SecretError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| SecretError::File{ path: path.display().to_string(), source: e })?;
+        non_empty(content.trim(), &format!("`{}`", path.display()))
+    }
+
+    /// Read a secret from an environment variable, surrounding whitespace is ignored.
+    pub fn from_env(name: &str) -> Result<Self, SecretError> {
+        Secret::from_env_value(name, std::env::var(name).ok().as_deref())
+    }
+
+    /// Like `from_env` for a value that was already looked up, `None` when
+    /// the variable is not set.
+    pub fn from_env_value(name: &str, value: Option<&str>) -> Result<Self, SecretError> {
+        let value = value.ok_or_else(|| SecretError::Env(name.to_string()))?;
+        non_empty(value.trim(), &format!("`{}`", name))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+fn non_empty(secret: &str, source: &str) -> Result<Secret, SecretError> {
+    if secret.is_empty() {
+        return Err(SecretError::Empty(source.to_string()));
+    }
+    Ok(Secret::new(secret))
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn secret_is_redacted_in_debug_and_display() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn secret_serializes_as_plain_string() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""hunter2""#);
+    }
+
+    #[test]
+    fn secret_from_file_is_trimmed() {
+        let path = std::env::temp_dir().join(format!("wartemis-secret-{}", std::process::id()));
+        fs::File::create(&path).unwrap().write_all(b"hunter2\n").unwrap();
+
+        let secret = Secret::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(secret.unwrap().expose(), "hunter2");
+    }
+
+    #[test]
+    fn secret_from_env() {
+        assert_eq!(Secret::from_env_value("TOKEN", Some("hunter2\n")).unwrap().expose(), "hunter2");
+        assert!(matches!(Secret::from_env_value("TOKEN", None), Err(SecretError::Env(_))));
+        assert!(matches!(Secret::from_env("WARTEMIS_TEST_SECRET_UNSET"), Err(SecretError::Env(_))));
+    }
+
+    #[test]
+    fn empty_secret_is_rejected() {
+        assert!(matches!(Secret::from_env_value("TOKEN", Some(" ")), Err(SecretError::Empty(_))));
+    }
+}
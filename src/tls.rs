@@ -0,0 +1,484 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+use thiserror::Error;
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+#[derive(Error,Debug)]
+pub enum TlsError {
+    #[error("read `{path}`: {source}")]
+    Read{
+        path: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("parse `{path}`: {reason}")]
+    Pem{
+        path: PathBuf,
+        reason: String,
+    },
+
+    #[error("no trusted root certificates, enable the system roots or add a CA file")]
+    NoRoots,
+
+    #[error("invalid server name `{0}`")]
+    ServerName(String),
+
+    #[error("connect to `{addr}`: {source}")]
+    Connect{
+        addr: String,
+        source: io::Error,
+    },
+
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Certificate chain and private key the client authenticates itself with.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ClientCertificate {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+/// How to set up TLS toward the server.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TlsConfig {
+    /// Trust the root certificates of the operating system.
+    pub system_roots: bool,
+    /// PEM bundle with extra trusted certificate authorities.
+    pub ca_file: Option<PathBuf>,
+    pub client_certificate: Option<ClientCertificate>,
+    /// Accept any server certificate. Only meant for testing against a
+    /// local server, never turned on by default.
+    pub insecure: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig{
+            system_roots: true,
+            ca_file: None,
+            client_certificate: None,
+            insecure: false,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn client_config(&self) -> Result<Arc<rustls::ClientConfig>, TlsError> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if self.insecure {
+            tracing::warn!("TLS certificate verification of the server is disabled");
+            builder.dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            builder.with_root_certificates(self.root_store()?)
+        };
+
+        let config = match &self.client_certificate {
+            Some(client) => builder.with_client_auth_cert(
+                read_certificates(&client.cert_file)?,
+                read_private_key(&client.key_file)?)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, TlsError> {
+        let mut roots = RootCertStore::empty();
+        if self.system_roots {
+            let native = rustls_native_certs::load_native_certs();
+            for e in &native.errors {
+                tracing::warn!(error = %e, "load system root certificates");
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+        if let Some(path) = &self.ca_file {
+            for cert in read_certificates(path)? {
+                roots.add(cert)
+                    .map_err(|e| TlsError::Pem{ path: path.clone(), reason: e.to_string() })?;
+            }
+        }
+
+        if roots.is_empty() {
+            return Err(TlsError::NoRoots);
+        }
+        Ok(roots)
+    }
+}
+
+/// Open a TCP connection to `addr` and complete the TLS handshake, verifying
+/// the certificate against `server_name`.
+pub fn connect(addr: &str, server_name: &str, config: Arc<rustls::ClientConfig>) -> Result<TlsStream, TlsError> {
+    let sock = TcpStream::connect(addr).map_err(|e| TlsError::Connect{ addr: addr.to_string(), source: e })?;
+    handshake(sock, addr, server_name, config)
+}
+
+/// Complete the TLS handshake over `sock`, already connected to `addr`.
+pub fn handshake(mut sock: TcpStream, addr: &str, server_name: &str, config: Arc<rustls::ClientConfig>) -> Result<TlsStream, TlsError> {
+    let name = ServerName::try_from(server_name.to_string())
+        .map_err(|_| TlsError::ServerName(server_name.to_string()))?;
+    let connect_error = |e| TlsError::Connect{ addr: addr.to_string(), source: e };
+
+    let mut conn = ClientConnection::new(config, name)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock).map_err(connect_error)?;
+    }
+    Ok(StreamOwned::new(conn, sock))
+}
+
+/// Split `stream` so one thread can read while another writes. Both halves
+/// share the connection, the reader only holds it while there is data.
+pub fn split(stream: TlsStream) -> io::Result<(TlsReader, TlsWriter)> {
+    let sock = stream.get_ref().try_clone()?;
+    let stream = Arc::new(Mutex::new(stream));
+    Ok((TlsReader{ stream: stream.clone(), sock }, TlsWriter{ stream }))
+}
+
+pub struct TlsReader {
+    stream: Arc<Mutex<TlsStream>>,
+    // Used to wait for data without locking the stream.
+    sock: TcpStream,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let mut stream = self.stream.lock().unwrap();
+                match stream.conn.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    // Servers often close without a close_notify, that is
+                    // a normal end for a stream of messages.
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                    result => return result,
+                }
+            }
+
+            self.sock.peek(&mut [0])?;
+            let mut stream = self.stream.lock().unwrap();
+            let StreamOwned{ conn, sock } = &mut *stream;
+            conn.read_tls(sock)?;
+            conn.process_new_packets().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            while conn.wants_write() {
+                conn.write_tls(sock)?;
+            }
+        }
+    }
+}
+
+pub struct TlsWriter {
+    stream: Arc<Mutex<TlsStream>>,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.lock().unwrap().flush()
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|e| TlsError::Read{ path: path.to_path_buf(), source: e })
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let pem_error = |reason: String| TlsError::Pem{ path: path.to_path_buf(), reason };
+
+    let certs = CertificateDer::pem_slice_iter(&read_pem(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(pem_error("no certificates found".to_string()));
+    }
+    Ok(certs)
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_slice(&read_pem(path)?)
+        .map_err(|e| TlsError::Pem{ path: path.to_path_buf(), reason: e.to_string() })
+}
+
+/// Verifier for the insecure mode: every certificate is accepted, but the
+/// handshake signatures are still checked.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime)
+            -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+            -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+            -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+
+    /// Certificate authority that signs the certificates of the stand-in
+    /// server and the client.
+    struct TestCa {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    struct Issued {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl TestCa {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let key = KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key).unwrap();
+            TestCa{ cert, key }
+        }
+
+        fn issue(&self, name: &str, usage: ExtendedKeyUsagePurpose) -> Issued {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.extended_key_usages = vec![usage];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            Issued{ cert, key }
+        }
+    }
+
+    /// A file in the temp directory, removed on drop so no keys are left
+    /// behind.
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_pem(name: &str, content: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("wartemis-tls-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+
+    fn server_config(server: Issued, client_ca: Option<&TestCa>) -> Arc<rustls::ServerConfig> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(ca.cert.der().clone()).unwrap();
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()
+                    .unwrap();
+                builder.with_client_cert_verifier(verifier)
+            },
+            None => builder.with_no_client_auth(),
+        };
+        let key = PrivateKeyDer::try_from(server.key.serialize_der()).unwrap();
+        Arc::new(builder.with_single_cert(vec![server.cert.der().clone()], key).unwrap())
+    }
+
+    /// Accept a single TLS connection and echo back the first line.
+    fn serve_echo(server: Issued, client_ca: Option<&TestCa>) -> String {
+        let config = server_config(server, client_ca);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(config).unwrap();
+            let mut stream = StreamOwned::new(conn, sock);
+            let mut line = String::new();
+            if BufReader::new(&mut stream).read_line(&mut line).is_ok() {
+                let _ = stream.write_all(line.as_bytes());
+                let _ = stream.flush();
+            }
+        });
+        addr
+    }
+
+    fn echo(stream: &mut TlsStream, line: &str) -> String {
+        stream.write_all(line.as_bytes()).unwrap();
+        stream.flush().unwrap();
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response).unwrap();
+        response
+    }
+
+    /// Client config that only trusts `ca`.
+    fn ca_config(ca: &TestCa, name: &str) -> Arc<rustls::ClientConfig> {
+        let ca_file = write_pem(name, &ca.cert.pem());
+        let config = TlsConfig{
+            system_roots: false,
+            ca_file: Some(ca_file.0.clone()),
+            ..TlsConfig::default()
+        };
+        config.client_config().unwrap()
+    }
+
+    #[test]
+    fn connect_with_custom_ca() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), None);
+
+        let config = ca_config(&ca, "ca.pem");
+        let mut stream = connect(&addr, "localhost", config).unwrap();
+        assert_eq!(echo(&mut stream, "{\"type\":\"Connected\"}\n"), "{\"type\":\"Connected\"}\n");
+    }
+
+    #[test]
+    fn unknown_ca_is_rejected() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), None);
+
+        let other_ca = TestCa::new();
+        let config = ca_config(&other_ca, "other-ca.pem");
+        assert!(matches!(connect(&addr, "localhost", config), Err(TlsError::Connect{ .. })));
+    }
+
+    #[test]
+    fn wrong_server_name_is_rejected() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), None);
+
+        let config = ca_config(&ca, "name-ca.pem");
+        assert!(connect(&addr, "wartemis.example", config).is_err());
+    }
+
+    #[test]
+    fn insecure_mode_accepts_any_certificate() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), None);
+
+        let config = TlsConfig{ system_roots: false, insecure: true, ..TlsConfig::default() };
+        let mut stream = connect(&addr, "localhost", config.client_config().unwrap()).unwrap();
+        assert_eq!(echo(&mut stream, "ping\n"), "ping\n");
+    }
+
+    #[test]
+    fn client_certificate_is_sent() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), Some(&ca));
+
+        let client = ca.issue("test_bot", ExtendedKeyUsagePurpose::ClientAuth);
+        let ca_file = write_pem("client-ca.pem", &ca.cert.pem());
+        let cert_file = write_pem("client.pem", &client.cert.pem());
+        let key_file = write_pem("client.key", &client.key.serialize_pem());
+        let config = TlsConfig{
+            system_roots: false,
+            ca_file: Some(ca_file.0.clone()),
+            client_certificate: Some(ClientCertificate{ cert_file: cert_file.0.clone(), key_file: key_file.0.clone() }),
+            ..TlsConfig::default()
+        };
+        let mut stream = connect(&addr, "localhost", config.client_config().unwrap()).unwrap();
+        assert_eq!(echo(&mut stream, "ping\n"), "ping\n");
+    }
+
+    #[test]
+    fn missing_client_certificate_is_rejected() {
+        let ca = TestCa::new();
+        let addr = serve_echo(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), Some(&ca));
+
+        let config = ca_config(&ca, "no-client-ca.pem");
+        // TLS 1.3 finishes the client side of the handshake before the server
+        // checks the certificate, so the rejection shows up on first read.
+        let mut stream = connect(&addr, "localhost", config).unwrap();
+        let e = stream.read(&mut [0; 16]).unwrap_err();
+        let alert = e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>());
+        assert_eq!(alert, Some(&rustls::Error::AlertReceived(rustls::AlertDescription::CertificateRequired)), "{}", e);
+    }
+
+    #[test]
+    fn client_talks_to_the_server_over_tls() {
+        use crate::channel;
+        use crate::client::Client;
+        use crate::handler;
+        use crate::transport::Transport;
+        use crate::transport::memory::MemoryTransport;
+        use crate::transport::tcp::TcpTransport;
+
+        let ca = TestCa::new();
+        let server_config = server_config(ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth), None);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ca_config(&ca, "client-e2e-ca.pem");
+        let server = TcpTransport::new(listener.local_addr().unwrap().to_string()).with_tls("localhost", config);
+        let (bot_end, bot) = MemoryTransport::pair();
+        bot.connect().unwrap();
+        let client_config = handler::ClientConfig::builder().game("test_game").name("test_bot").build().unwrap();
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(client_config));
+            let client: Client = Client::with_transports(handler, Arc::new(server), Arc::new(bot_end),
+                &channel::ChannelConfig::default()).unwrap();
+            client.start()
+        });
+
+        let (sock, _) = listener.accept().unwrap();
+        let mut stream = StreamOwned::new(rustls::ServerConnection::new(server_config).unwrap(), sock);
+        stream.write_all(b"{\"type\":\"Connected\"}\n").unwrap();
+        stream.flush().unwrap();
+        let mut register = String::new();
+        BufReader::new(&mut stream).read_line(&mut register).unwrap();
+        assert_eq!(register, "{\"type\":\"Register\",\"clientType\":\"bot\",\"game\":\"test_game\",\"name\":\"test_bot\"}\n");
+
+        stream.write_all(b"{\"type\":\"State\",\"turn\":1}\n").unwrap();
+        stream.flush().unwrap();
+        assert_eq!(bot.receive().unwrap().unwrap(), r#"{"type":"State","turn":1}"#);
+    }
+
+    #[test]
+    fn no_roots_is_an_error() {
+        let config = TlsConfig{ system_roots: false, ..TlsConfig::default() };
+        assert!(matches!(config.client_config(), Err(TlsError::NoRoots)));
+    }
+
+    #[test]
+    fn invalid_ca_file_is_reported() {
+        let ca_file = write_pem("invalid.pem", "not a certificate");
+        let config = TlsConfig{
+            system_roots: false,
+            ca_file: Some(ca_file.0.clone()),
+            ..TlsConfig::default()
+        };
+        assert!(matches!(config.client_config(), Err(TlsError::Pem{ .. })));
+    }
+}
@@ -0,0 +1,653 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use thiserror::Error;
+
+use crate::channel;
+use crate::client::Client;
+use crate::client_config::ClientConfigError;
+use crate::handler::{self, ClientConfig};
+use crate::referee::{Referee, RefereeConfig, RefereeError};
+use crate::rules::GameRules;
+use crate::transport::{Framing, Transport};
+use crate::transport::memory::MemoryTransport;
+use crate::transport::subprocess::SubprocessTransport;
+
+const INITIAL_ELO: f64 = 1500.0;
+const ELO_K: f64 = 32.0;
+
+#[derive(Error,Debug)]
+pub enum TournamentError {
+    #[error("a tournament needs at least two entrants")]
+    TooFewEntrants,
+
+    #[error("entrant `{name}`: {source}")]
+    Entrant{
+        name: String,
+        source: ClientConfigError,
+    },
+
+    #[error("game {game}: {source}")]
+    Game{
+        game: usize,
+        source: RefereeError,
+    },
+
+    #[error("write {path}: {source}")]
+    Write{
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Format {
+    /// Every entrant plays every other entrant.
+    RoundRobin,
+    /// Entrants with similar results play each other, for a fixed number of
+    /// rounds.
+    Swiss{ rounds: usize },
+}
+
+#[derive(Debug,Clone)]
+pub struct TournamentConfig {
+    pub format: Format,
+    /// Games per pairing, seats are swapped every other game.
+    pub games_per_pairing: usize,
+    /// Games played at the same time.
+    pub parallelism: usize,
+    /// Every game gets its own seed derived from this one.
+    pub seed: u64,
+    /// Where `results.txt` and a replay per game are written.
+    pub output_dir: Option<PathBuf>,
+    pub referee: RefereeConfig,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        TournamentConfig{
+            format: Format::RoundRobin,
+            games_per_pairing: 2,
+            parallelism: 4,
+            seed: 0,
+            output_dir: None,
+            referee: RefereeConfig::default(),
+        }
+    }
+}
+
+type BotFactory = dyn Fn() -> Arc<dyn Transport> + Send + Sync;
+
+/// A bot taking part in the tournament. It gets a fresh connection for
+/// every game.
+pub struct Entrant {
+    pub name: String,
+    bot: Box<BotFactory>,
+}
+
+impl Entrant {
+    pub fn new<F>(name: &str, bot: F) -> Self
+            where F: Fn() -> Arc<dyn Transport> + Send + Sync + 'static {
+        Entrant{ name: name.to_string(), bot: Box::new(bot) }
+    }
+
+    /// A bot executable, started once per game.
+    pub fn command(name: &str, command: Vec<String>, framing: Framing) -> Self {
+        Entrant::new(name, move || Arc::new(SubprocessTransport::new(command.clone()).with_framing(framing)))
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct GameRecord {
+    pub game: usize,
+    pub seed: u64,
+    /// Entrant indices, in seat order.
+    pub seats: Vec<usize>,
+    pub scores: Vec<f64>,
+    pub turns: usize,
+    /// Entrant that forfeited the game by failing to play, it lost.
+    pub forfeit: Option<usize>,
+}
+
+impl GameRecord {
+    /// Winning entrant, `None` on a draw.
+    pub fn winner(&self) -> Option<usize> {
+        let best = self.scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let mut best_seats = self.scores.iter().enumerate().filter(|(_, &score)| score == best);
+        match (best_seats.next(), best_seats.next()) {
+            (Some((seat, _)), None) => Some(self.seats[seat]),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Standing {
+    pub name: String,
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    /// Losses because the bot failed to play, like when it crashed.
+    pub forfeits: usize,
+    pub elo: f64,
+}
+
+impl Standing {
+    /// Draws count as half a win.
+    pub fn win_rate(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games as f64
+    }
+
+    fn points(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct TournamentResult {
+    pub games: Vec<GameRecord>,
+    /// In entrant order, see `ranking` for the table order.
+    pub standings: Vec<Standing>,
+}
+
+impl TournamentResult {
+    /// Standings from best to worst win rate, Elo breaks ties.
+    pub fn ranking(&self) -> Vec<&Standing> {
+        let mut ranking: Vec<&Standing> = self.standings.iter().collect();
+        ranking.sort_by(|a, b| b.win_rate().partial_cmp(&a.win_rate()).unwrap()
+            .then(b.elo.partial_cmp(&a.elo).unwrap()));
+        ranking
+    }
+}
+
+impl fmt::Display for TournamentResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.standings.iter().map(|standing| standing.name.len()).max().unwrap_or(0).max(4);
+        writeln!(f, "{:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>8}  {:>8}  {:>6}",
+            "name", "games", "wins", "draws", "losses", "forfeits", "win rate", "elo", width = width)?;
+        for standing in self.ranking() {
+            writeln!(f, "{:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>8}  {:>7.1}%  {:>6.0}",
+                standing.name, standing.games, standing.wins, standing.draws, standing.losses, standing.forfeits,
+                standing.win_rate() * 100.0, standing.elo, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+struct Job {
+    game: usize,
+    seed: u64,
+    seats: Vec<usize>,
+}
+
+/// Runs many two player matches between entrants with a local referee.
+pub struct Tournament<R> {
+    rules: R,
+    entrants: Vec<Entrant>,
+    config: TournamentConfig,
+}
+
+impl<R> Tournament<R>
+        where R: GameRules + Clone + Sync {
+    pub fn new(rules: R, entrants: Vec<Entrant>, config: TournamentConfig) -> Self {
+        Tournament{ rules, entrants, config }
+    }
+
+    pub fn run(&self) -> Result<TournamentResult, TournamentError> {
+        if self.entrants.len() < 2 {
+            return Err(TournamentError::TooFewEntrants);
+        }
+        let client_configs = self.client_configs()?;
+        if let Some(dir) = &self.config.output_dir {
+            fs::create_dir_all(dir).map_err(|e| TournamentError::Write{ path: dir.clone(), source: e })?;
+        }
+
+        let mut games = Vec::new();
+        match self.config.format {
+            Format::RoundRobin => {
+                let mut pairings = Vec::new();
+                for first in 0..self.entrants.len() {
+                    for second in first + 1..self.entrants.len() {
+                        pairings.push((first, second));
+                    }
+                }
+                games = self.play_round(&client_configs, &pairings, 0)?;
+            },
+            Format::Swiss{ rounds } => {
+                let mut played = HashSet::new();
+                for _ in 0..rounds {
+                    let standings = standings(&self.entrants, &games);
+                    let pairings = swiss_pairings(&standings, &played);
+                    played.extend(pairings.iter().cloned());
+                    let round = self.play_round(&client_configs, &pairings, games.len())?;
+                    games.extend(round);
+                }
+            },
+        }
+
+        let result = TournamentResult{ standings: standings(&self.entrants, &games), games };
+        if let Some(dir) = &self.config.output_dir {
+            let path = dir.join("results.txt");
+            fs::write(&path, result.to_string()).map_err(|e| TournamentError::Write{ path, source: e })?;
+        }
+        Ok(result)
+    }
+
+    fn client_configs(&self) -> Result<Vec<ClientConfig>, TournamentError> {
+        self.entrants.iter()
+            .map(|entrant| ClientConfig::builder()
+                .game(&self.config.referee.game)
+                .name(&entrant.name)
+                .build()
+                .map_err(|e| TournamentError::Entrant{ name: entrant.name.clone(), source: e }))
+            .collect()
+    }
+
+    /// Play every pairing `games_per_pairing` times, `parallelism` games at
+    /// a time. Games are numbered from `first_game`.
+    fn play_round(&self, client_configs: &[ClientConfig], pairings: &[(usize, usize)], first_game: usize)
+            -> Result<Vec<GameRecord>, TournamentError> {
+        let (job_sender, jobs) = crossbeam_channel::unbounded();
+        for (index, &(first, second)) in pairings.iter().enumerate() {
+            for game in 0..self.config.games_per_pairing {
+                let seats = if game % 2 == 0 { vec![first, second] } else { vec![second, first] };
+                let game = first_game + index * self.config.games_per_pairing + game;
+                let _ = job_sender.send(Job{ game, seed: game_seed(self.config.seed, game), seats });
+            }
+        }
+        drop(job_sender);
+
+        let (result_sender, results) = crossbeam_channel::unbounded();
+        thread::scope(|scope| {
+            for _ in 0..self.config.parallelism.max(1) {
+                let jobs = jobs.clone();
+                let result_sender = result_sender.clone();
+                scope.spawn(move || {
+                    for job in jobs {
+                        let _ = result_sender.send(self.play(client_configs, job));
+                    }
+                });
+            }
+        });
+        drop(result_sender);
+
+        let mut records = results.iter().collect::<Result<Vec<_>, _>>()?;
+        records.sort_by_key(|record| record.game);
+        Ok(records)
+    }
+
+    fn play(&self, client_configs: &[ClientConfig], job: Job) -> Result<GameRecord, TournamentError> {
+        let mut connections: Vec<Arc<dyn Transport>> = Vec::new();
+        let mut bots = Vec::new();
+        let mut clients = Vec::new();
+        for &entrant in &job.seats {
+            let (referee_end, client_end) = MemoryTransport::pair();
+            let bot = (self.entrants[entrant].bot)();
+            connections.push(Arc::new(referee_end));
+            bots.push(bot.clone());
+
+            let client_config = client_configs[entrant].clone();
+            clients.push(thread::spawn(move || {
+                let handler = Box::new(handler::MessageHandler::new(client_config));
+                let client: Client = match Client::with_transports(handler, Arc::new(client_end), bot, &channel::ChannelConfig::default()) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "start bot");
+                        return;
+                    },
+                };
+                let _ = client.start();
+            }));
+        }
+
+        tracing::info!(game = job.game, seed = job.seed, "starting game");
+        let config = RefereeConfig{ seed: Some(job.seed), ..self.config.referee.clone() };
+        let referee = Referee::new(self.rules.clone(), config);
+        let result = referee.run(connections);
+        // The referee closed its side, which ends the clients once they
+        // passed the `GameEnd` on. After a failed game a bot may be stuck, so
+        // it is closed right away.
+        if result.is_err() {
+            close(&bots);
+        }
+        for client in clients {
+            let _ = client.join();
+        }
+        close(&bots);
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => return match e.player() {
+                Some(seat) => {
+                    tracing::warn!(game = job.game, entrant = %self.entrants[job.seats[seat]].name, error = %e, "forfeit");
+                    Ok(forfeit(job, seat))
+                },
+                None => Err(TournamentError::Game{ game: job.game, source: e }),
+            },
+        };
+
+        if let Some(dir) = &self.config.output_dir {
+            let path = dir.join(format!("game-{:04}.json", job.game));
+            let replay = serde_json::to_string_pretty(&result.replay).expect("replays serialize");
+            fs::write(&path, replay).map_err(|e| TournamentError::Write{ path, source: e })?;
+        }
+
+        Ok(GameRecord{ game: job.game, seed: job.seed, seats: job.seats, scores: result.scores, turns: result.turns, forfeit: None })
+    }
+}
+
+/// Record of a game the player in `seat` failed to play, the others win.
+fn forfeit(job: Job, seat: usize) -> GameRecord {
+    let scores = (0..job.seats.len()).map(|other| if other == seat { 0.0 } else { 1.0 }).collect();
+    GameRecord{ game: job.game, seed: job.seed, forfeit: Some(job.seats[seat]), seats: job.seats, scores, turns: 0 }
+}
+
+fn close(transports: &[Arc<dyn Transport>]) {
+    for transport in transports {
+        let _ = transport.close();
+    }
+}
+
+/// Seed of one game, spread out with SplitMix64 so neighbouring games
+/// don't get similar seeds.
+fn game_seed(seed: u64, game: usize) -> u64 {
+    let mut z = seed.wrapping_add((game as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Standings after `games`, Elo is updated game by game in game order.
+fn standings(entrants: &[Entrant], games: &[GameRecord]) -> Vec<Standing> {
+    let mut standings: Vec<Standing> = entrants.iter()
+        .map(|entrant| Standing{ name: entrant.name.clone(), games: 0, wins: 0, draws: 0, losses: 0, forfeits: 0, elo: INITIAL_ELO })
+        .collect();
+
+    for game in games {
+        let (first, second) = (game.seats[0], game.seats[1]);
+        let first_score = match game.winner() {
+            Some(winner) if winner == first => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+        for &(player, score) in &[(first, first_score), (second, 1.0 - first_score)] {
+            let standing = &mut standings[player];
+            standing.games += 1;
+            if score == 1.0 {
+                standing.wins += 1;
+            } else if score == 0.0 {
+                standing.losses += 1;
+            } else {
+                standing.draws += 1;
+            }
+        }
+
+        if let Some(entrant) = game.forfeit {
+            standings[entrant].forfeits += 1;
+        }
+
+        let expected = 1.0 / (1.0 + 10f64.powf((standings[second].elo - standings[first].elo) / 400.0));
+        let change = ELO_K * (first_score - expected);
+        standings[first].elo += change;
+        standings[second].elo -= change;
+    }
+    standings
+}
+
+/// Pair entrants with similar points, avoiding rematches where possible.
+/// With an odd number of entrants the lowest ranked one sits out.
+fn swiss_pairings(standings: &[Standing], played: &HashSet<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..standings.len()).collect();
+    order.sort_by(|&a, &b| standings[b].points().partial_cmp(&standings[a].points()).unwrap().then(a.cmp(&b)));
+
+    let has_played = |a: usize, b: usize| played.contains(&(a.min(b), a.max(b)));
+    let mut pairings = Vec::new();
+    while order.len() >= 2 {
+        let first = order.remove(0);
+        let opponent = order.iter().position(|&other| !has_played(first, other)).unwrap_or(0);
+        let second = order.remove(opponent);
+        pairings.push((first.min(second), first.max(second)));
+    }
+    pairings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message as msg;
+    use crate::bot::Bot;
+    use crate::referee::{bot_connection, reproduce, Replay};
+    use crate::rules::goofspiel::{Bid, Goofspiel, View};
+    use std::time::Duration;
+
+    fn config(format: Format) -> TournamentConfig {
+        TournamentConfig{
+            format,
+            referee: RefereeConfig{
+                game: "goofspiel".to_string(),
+                action_timeout: Duration::from_secs(5),
+                ..RefereeConfig::default()
+            },
+            ..TournamentConfig::default()
+        }
+    }
+
+    /// Bids the card `0` picks.
+    struct Strategy(fn(&View) -> u32);
+
+    impl Bot for Strategy {
+        type State = View;
+        type Action = Bid;
+
+        fn on_state(&mut self, view: &View) -> Bid {
+            Bid{ card: (self.0)(view) }
+        }
+    }
+
+    /// An in-process bot the entrant's client drives over a memory transport.
+    fn entrant(name: &str, strategy: fn(&View) -> u32) -> Entrant {
+        let bot_name = name.to_string();
+        Entrant::new(name, move || bot_connection(Strategy(strategy), &bot_name, "goofspiel"))
+    }
+
+    fn match_prize(view: &View) -> u32 {
+        if view.hand.contains(&view.prize) { view.prize } else { view.hand[0] }
+    }
+
+    fn highest(view: &View) -> u32 {
+        *view.hand.iter().max().unwrap()
+    }
+
+    fn lowest(view: &View) -> u32 {
+        view.hand[0]
+    }
+
+    fn record(seats: Vec<usize>, scores: Vec<f64>) -> GameRecord {
+        GameRecord{ game: 0, seed: 0, seats, scores, turns: 1, forfeit: None }
+    }
+
+    fn output_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wartemis-tournament-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_robin_plays_every_pairing() {
+        let dir = output_dir("round-robin");
+        let tournament = Tournament::new(Goofspiel::new(5), vec![
+            entrant("matcher", match_prize),
+            entrant("highest", highest),
+            entrant("lowest", lowest),
+        ], TournamentConfig{ output_dir: Some(dir.clone()), ..config(Format::RoundRobin) });
+        let result = tournament.run().unwrap();
+
+        assert_eq!(result.games.len(), 6);
+        assert!(result.standings.iter().all(|standing| standing.games == 4));
+        let seeds: HashSet<u64> = result.games.iter().map(|game| game.seed).collect();
+        assert_eq!(seeds.len(), 6);
+
+        // Matching the prize and bidding low tie each other and both beat
+        // bidding high.
+        assert_eq!(result.standings[0].win_rate(), 0.75);
+        assert_eq!(result.standings[2].win_rate(), 0.75);
+        let ranking = result.ranking();
+        assert_eq!(ranking[2].name, "highest");
+        assert_eq!(ranking[2].win_rate(), 0.0);
+        assert!(ranking[2].elo < INITIAL_ELO);
+
+        let table = fs::read_to_string(dir.join("results.txt")).unwrap();
+        assert!(table.lines().last().unwrap().starts_with("highest"), "{}", table);
+        let replay: Replay = serde_json::from_str(&fs::read_to_string(dir.join("game-0005.json")).unwrap()).unwrap();
+        assert_eq!(replay.turns.len(), 5);
+        assert_eq!(replay.seed, result.games[5].seed);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn games_are_reproducible_from_their_replay() {
+        let dir = output_dir("reproduce");
+        let rules = Goofspiel::new(8).shuffled();
+        let tournament = Tournament::new(rules.clone(), vec![
+            entrant("matcher", match_prize),
+            entrant("highest", highest),
+        ], TournamentConfig{ seed: 3, output_dir: Some(dir.clone()), ..config(Format::RoundRobin) });
+        let result = tournament.run().unwrap();
+
+        for game in &result.games {
+            let replay: Replay = serde_json::from_str(&fs::read_to_string(dir.join(format!("game-{:04}.json", game.game))).unwrap()).unwrap();
+            let state = reproduce(&rules, &replay).unwrap();
+            assert_eq!(state.scores.iter().map(|&score| score as f64).collect::<Vec<_>>(), game.scores);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bot_executables_take_part() {
+        // Always bids a card it doesn't have, so plays its lowest card.
+        let script = r#"while read line; do echo '{"type":"Action","card":0}'; done"#;
+        let tournament = Tournament::new(Goofspiel::new(3), vec![
+            entrant("highest", highest),
+            Entrant::command("script", vec!["sh".to_string(), "-c".to_string(), script.to_string()], Framing::Newline),
+        ], config(Format::RoundRobin));
+        let result = tournament.run().unwrap();
+
+        // Low bids take the last and biggest prize.
+        assert_eq!(result.standings[0].losses, 2);
+        assert_eq!(result.standings[1].wins, 2);
+    }
+
+    #[test]
+    fn failing_bot_forfeits_and_the_tournament_goes_on() {
+        let dir = output_dir("forfeit");
+        let tournament = Tournament::new(Goofspiel::new(3), vec![
+            entrant("lowest", lowest),
+            Entrant::command("missing", vec!["/does/not/exist".to_string()], Framing::Newline),
+            entrant("highest", highest),
+        ], TournamentConfig{ output_dir: Some(dir.clone()), ..config(Format::RoundRobin) });
+        let result = tournament.run().unwrap();
+
+        assert_eq!(result.games.len(), 6);
+        let missing = &result.standings[1];
+        assert_eq!((missing.games, missing.losses, missing.forfeits), (4, 4, 4));
+        assert_eq!(result.standings[0].forfeits + result.standings[2].forfeits, 0);
+        assert!(result.games.iter().filter(|game| game.seats.contains(&1)).all(|game| game.forfeit == Some(1)));
+
+        let table = fs::read_to_string(dir.join("results.txt")).unwrap();
+        assert!(table.lines().last().unwrap().starts_with("missing"), "{}", table);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bots_receive_the_result() {
+        struct Reporting(crossbeam_channel::Sender<()>);
+
+        impl Bot for Reporting {
+            type State = View;
+            type Action = Bid;
+
+            fn on_state(&mut self, view: &View) -> Bid {
+                Bid{ card: lowest(view) }
+            }
+
+            fn on_game_end(&mut self, _result: &msg::MessageContent) {
+                let _ = self.0.send(());
+            }
+        }
+
+        let (results, received) = crossbeam_channel::unbounded();
+        let reporting = Entrant::new("reporting", move || bot_connection(Reporting(results.clone()), "reporting", "goofspiel"));
+        let tournament = Tournament::new(Goofspiel::new(3), vec![reporting, entrant("highest", highest)], config(Format::RoundRobin));
+        let result = tournament.run().unwrap();
+
+        // The last results may still be on their way to the bot thread.
+        for _ in &result.games {
+            received.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn swiss_rounds_avoid_rematches() {
+        let tournament = Tournament::new(Goofspiel::new(3), vec![
+            entrant("a", match_prize),
+            entrant("b", highest),
+            entrant("c", lowest),
+            entrant("d", match_prize),
+        ], TournamentConfig{ games_per_pairing: 1, ..config(Format::Swiss{ rounds: 3 }) });
+        let result = tournament.run().unwrap();
+
+        assert_eq!(result.games.len(), 6);
+        let pairings: HashSet<(usize, usize)> = result.games.iter()
+            .map(|game| (game.seats[0].min(game.seats[1]), game.seats[0].max(game.seats[1])))
+            .collect();
+        assert_eq!(pairings.len(), 6);
+    }
+
+    #[test]
+    fn swiss_pairs_similar_results() {
+        let entrants: Vec<Entrant> = ["a", "b", "c", "d", "e"].iter().map(|name| entrant(name, lowest)).collect();
+        let games = vec![record(vec![0, 1], vec![1.0, 0.0]), record(vec![2, 3], vec![0.0, 1.0])];
+        let mut played = HashSet::new();
+        played.extend(vec![(0, 1), (2, 3)]);
+
+        let pairings = swiss_pairings(&standings(&entrants, &games), &played);
+        // The winners meet, then the losers, `e` sits out.
+        assert_eq!(pairings, vec![(0, 3), (1, 2)]);
+    }
+
+    #[test]
+    fn draws_and_elo() {
+        let entrants = vec![entrant("a", lowest), entrant("b", lowest)];
+        let standings = standings(&entrants, &[
+            record(vec![0, 1], vec![2.0, 2.0]),
+            record(vec![1, 0], vec![1.0, 3.0]),
+        ]);
+
+        assert_eq!((standings[0].wins, standings[0].draws, standings[0].losses), (1, 1, 0));
+        assert_eq!(standings[0].win_rate(), 0.75);
+        assert_eq!(standings[1].win_rate(), 0.25);
+        assert_eq!(standings[0].elo, INITIAL_ELO + 16.0);
+        assert_eq!(standings[0].elo + standings[1].elo, 2.0 * INITIAL_ELO);
+    }
+
+    #[test]
+    fn too_few_entrants() {
+        let tournament = Tournament::new(Goofspiel::new(3), vec![entrant("alone", lowest)], config(Format::RoundRobin));
+        assert!(matches!(tournament.run(), Err(TournamentError::TooFewEntrants)));
+    }
+
+    #[test]
+    fn invalid_entrant_name() {
+        let tournament = Tournament::new(Goofspiel::new(3), vec![
+            entrant("has space", lowest),
+            entrant("fine", lowest),
+        ], config(Format::RoundRobin));
+        assert!(matches!(tournament.run(), Err(TournamentError::Entrant{ .. })));
+    }
+}
@@ -0,0 +1,336 @@
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam_channel::Receiver;
+use thiserror::Error;
+
+use crate::channel;
+use crate::message as msg;
+
+pub mod framing;
+pub mod memory;
+pub mod multiplex;
+pub mod stdio;
+pub mod subprocess;
+pub mod tcp;
+
+pub use framing::Framing;
+use framing::Frame;
+
+#[derive(Error,Debug)]
+pub enum TransportError {
+    #[error("connect to `{target}`: {source}")]
+    Connect{
+        target: String,
+        source: io::Error,
+    },
+
+    #[error("transport is not connected")]
+    NotConnected,
+
+    #[error("transport is closed")]
+    Closed,
+
+    #[error("message contains a newline")]
+    Newline,
+
+    #[error(transparent)]
+    Message(#[from] msg::MessageError),
+
+    #[cfg(feature = "tls")]
+    #[error(transparent)]
+    Tls(#[from] crate::tls::TlsError),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Health {
+    NotConnected,
+    Healthy,
+    Closed,
+    Failed(String),
+}
+
+/// A connection to the server or to a bot that carries whole messages.
+/// Sending and receiving can happen at the same time from different threads.
+pub trait Transport: Send + Sync {
+    fn connect(&self) -> Result<(), TransportError>;
+    fn send(&self, message: &str) -> Result<(), TransportError>;
+    /// Wait for the next message, `None` once the other side closed.
+    fn receive(&self) -> Result<Option<String>, TransportError>;
+    /// Close the connection, a pending `receive` returns once the other side
+    /// noticed.
+    fn close(&self) -> Result<(), TransportError>;
+    fn health(&self) -> Health;
+}
+
+/// Channels between a connected transport and a `Client`. `incoming` is one
+/// of the client's inputs and `outgoing` is added as the matching output.
+pub struct Attached {
+    pub incoming: Receiver<String>,
+    pub outgoing: channel::OutputSender,
+    /// Finishes once `outgoing` and its clones are dropped and every queued
+    /// message went out. Join it before closing the transport.
+    pub writer: thread::JoinHandle<()>,
+}
+
+/// Move messages between `transport` and a pair of channels on two threads.
+pub fn attach(transport: Arc<dyn Transport>, config: &channel::ChannelConfig) -> Attached {
+    let (incoming_sender, incoming) = channel::bounded(config);
+    let (outgoing, outgoing_receiver) = channel::bounded(config);
+
+    let reader = transport.clone();
+    thread::spawn(move || loop {
+        match reader.receive() {
+            Ok(Some(message)) => if incoming_sender.send(message).is_err() {
+                return;
+            },
+            Ok(None) => return,
+            Err(TransportError::Message(e)) => tracing::warn!(error = %e, "dropping message"),
+            Err(e) => {
+                tracing::warn!(error = %e, "receive from transport");
+                return;
+            },
+        }
+    });
+
+    let writer = thread::spawn(move || {
+        for message in outgoing_receiver {
+            match transport.send(&message) {
+                Ok(()) => (),
+                Err(TransportError::Newline) => tracing::warn!("dropping message that contains a newline"),
+                Err(e) => {
+                    tracing::warn!(error = %e, "send to transport");
+                    return;
+                },
+            }
+        }
+    });
+
+    Attached{ incoming, outgoing, writer }
+}
+
+type Reader = Box<dyn BufRead + Send>;
+type Writer = Box<dyn Write + Send>;
+
+/// Frames messages over a pair of byte streams, shared by the stream based
+/// transports. Every `open` gets its own reader so a read still pending on
+/// an earlier stream never blocks reopening.
+pub(crate) struct Framed {
+    framing: Framing,
+    reader: Mutex<Option<Arc<Mutex<Reader>>>>,
+    writer: Mutex<Option<Writer>>,
+    health: Mutex<Health>,
+}
+
+impl Framed {
+    pub(crate) fn new(framing: Framing) -> Self {
+        Framed{
+            framing,
+            reader: Mutex::new(None),
+            writer: Mutex::new(None),
+            health: Mutex::new(Health::NotConnected),
+        }
+    }
+
+    pub(crate) fn open(&self, reader: Reader, writer: Writer) {
+        *self.reader.lock().unwrap() = Some(Arc::new(Mutex::new(reader)));
+        *self.writer.lock().unwrap() = Some(writer);
+        self.set_health(Health::Healthy);
+    }
+
+    pub(crate) fn send(&self, message: &str) -> Result<(), TransportError> {
+        let mut writer = self.writer.lock().unwrap();
+        let writer = writer.as_mut().ok_or_else(|| self.unavailable())?;
+        self.framing.write(writer, message).map_err(|e| match e {
+            TransportError::Io(e) => self.failed(e),
+            e => e,
+        })
+    }
+
+    /// Read the next frame. After a malformed frame that can't be recovered
+    /// from the reader is dropped, so later calls fail instead of hanging.
+    /// A read that finishes after the stream was reopened leaves the health
+    /// of the new stream alone.
+    pub(crate) fn receive(&self) -> Result<Option<String>, TransportError> {
+        let current = self.reader.lock().unwrap().clone().ok_or_else(|| self.unavailable())?;
+        let frame = self.framing.read(&mut *current.lock().unwrap());
+        let mut reader = self.reader.lock().unwrap();
+        let is_current = reader.as_ref().is_some_and(|reader| Arc::ptr_eq(reader, &current));
+
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) if is_current => return Err(self.failed(e)),
+            Err(e) => return Err(e.into()),
+        };
+        match frame {
+            Frame::Message(message) => Ok(Some(message)),
+            Frame::End => {
+                if is_current {
+                    self.set_health(Health::Closed);
+                }
+                Ok(None)
+            },
+            Frame::Malformed(e) => Err(e.into()),
+            Frame::Fatal(e) => {
+                if is_current {
+                    reader.take();
+                    self.set_health(Health::Failed(e.to_string()));
+                }
+                Err(e.into())
+            },
+        }
+    }
+
+    /// Stop writing. The reader is left alone, a pending read holds on to
+    /// it until the underlying stream is closed, without blocking `open`.
+    pub(crate) fn close(&self) {
+        self.writer.lock().unwrap().take();
+        self.set_health(Health::Closed);
+    }
+
+    pub(crate) fn health(&self) -> Health {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_health(&self, health: Health) {
+        *self.health.lock().unwrap() = health;
+    }
+
+    fn failed(&self, e: io::Error) -> TransportError {
+        self.set_health(Health::Failed(e.to_string()));
+        e.into()
+    }
+
+    fn unavailable(&self) -> TransportError {
+        match self.health() {
+            Health::NotConnected => TransportError::NotConnected,
+            _ => TransportError::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn framed(framing: Framing, input: &'static [u8]) -> (Framed, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let framed = Framed::new(framing);
+        framed.open(Box::new(Cursor::new(input)), Box::new(SharedBuffer(output.clone())));
+        (framed, output)
+    }
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn end_of_stream_closes() {
+        let (framed, _) = framed(Framing::Newline, b"{\"type\":\"State\"}\n");
+        assert_eq!(framed.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+        assert!(framed.receive().unwrap().is_none());
+        assert_eq!(framed.health(), Health::Closed);
+    }
+
+    #[test]
+    fn malformed_frame_is_a_message_error() {
+        let (framed, _) = framed(Framing::Newline, b"\xff\n{}\n");
+        assert!(matches!(framed.receive(), Err(TransportError::Message(msg::MessageError::Frame(_)))));
+        assert_eq!(framed.receive().unwrap().unwrap(), "{}");
+    }
+
+    #[test]
+    fn fatal_frame_stops_reading() {
+        let (framed, _) = framed(Framing::Json, b"{\"type\": ");
+        assert!(matches!(framed.receive(), Err(TransportError::Message(msg::MessageError::Frame(_)))));
+        assert!(matches!(framed.health(), Health::Failed(_)));
+        assert!(matches!(framed.receive(), Err(TransportError::Closed)));
+    }
+
+    /// Blocks in `read` until the test sends bytes, telling it when a read
+    /// starts.
+    struct ChannelReader {
+        bytes: Receiver<Vec<u8>>,
+        reading: crossbeam_channel::Sender<()>,
+    }
+
+    impl io::Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let _ = self.reading.send(());
+            match self.bytes.recv() {
+                Ok(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                },
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn reopen_does_not_wait_for_a_pending_read() {
+        let framed = Arc::new(Framed::new(Framing::Newline));
+        let (old_snd, bytes) = crossbeam_channel::unbounded();
+        let (reading, started) = crossbeam_channel::unbounded();
+        framed.open(Box::new(io::BufReader::new(ChannelReader{ bytes, reading })), Box::new(io::sink()));
+
+        let pending = framed.clone();
+        let old_read = thread::spawn(move || pending.receive());
+        started.recv().unwrap();
+        framed.close();
+
+        framed.open(Box::new(Cursor::new(b"new\n")), Box::new(io::sink()));
+        assert_eq!(framed.receive().unwrap().unwrap(), "new");
+
+        // The old stream ending doesn't close the new one.
+        drop(old_snd);
+        assert!(old_read.join().unwrap().unwrap().is_none());
+        assert_eq!(framed.health(), Health::Healthy);
+    }
+
+    #[test]
+    fn send_uses_the_framing() {
+        let (framed, output) = framed(Framing::LengthPrefixed, b"");
+        framed.send("{}").unwrap();
+        assert_eq!(*output.lock().unwrap(), b"\x00\x00\x00\x02{}");
+    }
+
+    #[test]
+    fn unopened_stream_is_not_connected() {
+        let framed = Framed::new(Framing::Newline);
+        assert!(matches!(framed.send("{}"), Err(TransportError::NotConnected)));
+        framed.close();
+        assert!(matches!(framed.send("{}"), Err(TransportError::Closed)));
+    }
+
+    #[test]
+    fn attach_moves_messages_both_ways() {
+        let (ours, theirs) = memory::MemoryTransport::pair();
+        let ours: Arc<dyn Transport> = Arc::new(ours);
+        ours.connect().unwrap();
+        theirs.connect().unwrap();
+
+        let attached = attach(ours, &channel::ChannelConfig::default());
+        theirs.send(r#"{"type":"State"}"#).unwrap();
+        assert_eq!(attached.incoming.recv().unwrap(), r#"{"type":"State"}"#);
+
+        attached.outgoing.send(r#"{"type":"Action"}"#.to_string()).unwrap();
+        assert_eq!(theirs.receive().unwrap().unwrap(), r#"{"type":"Action"}"#);
+
+        theirs.close().unwrap();
+        assert!(attached.incoming.recv().is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::message as msg;
+
+use super::TransportError;
+
+/// Largest frame that is accepted, so a corrupt length or a never ending
+/// object can't exhaust memory.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// How messages are delimited on a byte stream.
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// One message per line, messages can't contain a newline.
+    #[default]
+    Newline,
+    /// Every message is preceded by its length in bytes as a big endian `u32`.
+    LengthPrefixed,
+    /// Messages are split on complete top-level JSON objects, so they can
+    /// span several lines. Other output between objects is skipped.
+    Json,
+}
+
+/// Result of reading one frame. A malformed frame that leaves the stream in
+/// an unknown state is `Fatal`, nothing can be read after it.
+pub(crate) enum Frame {
+    Message(String),
+    End,
+    Malformed(msg::MessageError),
+    Fatal(msg::MessageError),
+}
+
+fn malformed(reason: String) -> msg::MessageError {
+    msg::MessageError::Frame(reason)
+}
+
+fn to_string(frame: Vec<u8>) -> Frame {
+    match String::from_utf8(frame) {
+        Ok(message) => Frame::Message(message),
+        Err(e) => Frame::Malformed(malformed(e.to_string())),
+    }
+}
+
+impl Framing {
+    pub(crate) fn read(&self, reader: &mut dyn BufRead) -> io::Result<Frame> {
+        match self {
+            Framing::Newline => read_line(reader),
+            Framing::LengthPrefixed => read_length_prefixed(reader),
+            Framing::Json => read_json(reader),
+        }
+    }
+
+    pub(crate) fn write(&self, writer: &mut dyn Write, message: &str) -> Result<(), TransportError> {
+        match self {
+            Framing::Newline | Framing::Json => {
+                if *self == Framing::Newline && message.contains('\n') {
+                    return Err(TransportError::Newline);
+                }
+                writer.write_all(message.as_bytes())?;
+                writer.write_all(b"\n")?;
+            },
+            Framing::LengthPrefixed => {
+                let len = u32::try_from(message.len()).ok()
+                    .filter(|len| *len as usize <= MAX_FRAME_LEN)
+                    .ok_or_else(|| malformed(format!("message of {} bytes is too large", message.len())))?;
+                writer.write_all(&len.to_be_bytes())?;
+                writer.write_all(message.as_bytes())?;
+            },
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+/// A line may arrive over any number of reads. Empty lines are skipped.
+fn read_line(reader: &mut dyn BufRead) -> io::Result<Frame> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // One more byte than the limit leaves room for the newline.
+        if reader.take(MAX_FRAME_LEN as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+            return Ok(Frame::End);
+        }
+        if line.len() > MAX_FRAME_LEN && line.last() != Some(&b'\n') {
+            return Ok(Frame::Fatal(malformed(format!("line is larger than {} bytes", MAX_FRAME_LEN))));
+        }
+
+        while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+            line.pop();
+        }
+        if !line.is_empty() {
+            return Ok(to_string(line));
+        }
+    }
+}
+
+fn read_length_prefixed(reader: &mut dyn BufRead) -> io::Result<Frame> {
+    let mut header = [0; 4];
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..])? {
+            0 if read == 0 => return Ok(Frame::End),
+            0 => return Ok(Frame::Fatal(malformed(format!("stream ended inside a {} byte length", read)))),
+            n => read += n,
+        }
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Ok(Frame::Fatal(malformed(format!("frame of {} bytes is larger than {}", len, MAX_FRAME_LEN))));
+    }
+
+    let mut frame = Vec::with_capacity(len);
+    Read::take(&mut *reader, len as u64).read_to_end(&mut frame)?;
+    if frame.len() < len {
+        return Ok(Frame::Fatal(malformed(format!("stream ended after {} of {} bytes", frame.len(), len))));
+    }
+    Ok(to_string(frame))
+}
+
+/// Scan for the end of a top-level object, keeping track of strings so
+/// braces inside them don't count. Anything else between objects, like a
+/// stray print from the bot, is skipped up to the next `{` and reported.
+fn read_json(reader: &mut dyn BufRead) -> io::Result<Frame> {
+    let mut frame = Vec::new();
+    let mut skipped = Vec::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(if !skipped.is_empty() {
+                Frame::Malformed(skipped_error(&skipped))
+            } else if frame.is_empty() {
+                Frame::End
+            } else {
+                Frame::Fatal(malformed(format!("stream ended inside an object after {} bytes", frame.len())))
+            });
+        }
+
+        let mut used = 0;
+        // `Some(true)` once the object is complete, `Some(false)` at the `{`
+        // after skipped bytes, which is left for the next read.
+        let mut outcome = None;
+        for &b in buf {
+            if depth == 0 {
+                match b {
+                    b'{' if !skipped.is_empty() => {
+                        outcome = Some(false);
+                        break;
+                    },
+                    b'{' => depth = 1,
+                    b' ' | b'\t' | b'\r' | b'\n' if skipped.is_empty() => {
+                        used += 1;
+                        continue;
+                    },
+                    _ => {
+                        used += 1;
+                        skipped.push(b);
+                        continue;
+                    },
+                }
+            } else if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => (),
+                }
+            } else {
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => (),
+                }
+            }
+
+            used += 1;
+            frame.push(b);
+            if depth == 0 {
+                outcome = Some(true);
+                break;
+            }
+        }
+        reader.consume(used);
+
+        match outcome {
+            Some(true) => return Ok(to_string(frame)),
+            Some(false) => return Ok(Frame::Malformed(skipped_error(&skipped))),
+            None => (),
+        }
+        if skipped.len() > MAX_FRAME_LEN {
+            return Ok(Frame::Malformed(skipped_error(&skipped)));
+        }
+        if frame.len() > MAX_FRAME_LEN {
+            return Ok(Frame::Fatal(malformed(format!("object is larger than {} bytes", MAX_FRAME_LEN))));
+        }
+    }
+}
+
+fn skipped_error(skipped: &[u8]) -> msg::MessageError {
+    let text: String = String::from_utf8_lossy(skipped).trim_end().chars().take(80).collect();
+    malformed(format!("skipped {} bytes that are not an object: `{}`", skipped.len(), text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor, Read};
+
+    fn read_all(framing: Framing, input: &[u8]) -> Vec<Result<String, String>> {
+        // A tiny buffer makes every frame arrive over several reads.
+        let mut reader = BufReader::with_capacity(3, Cursor::new(input.to_vec()));
+        let mut frames = Vec::new();
+        loop {
+            match framing.read(&mut reader).unwrap() {
+                Frame::Message(message) => frames.push(Ok(message)),
+                Frame::Malformed(e) => frames.push(Err(e.to_string())),
+                Frame::Fatal(e) => {
+                    frames.push(Err(e.to_string()));
+                    return frames;
+                },
+                Frame::End => return frames,
+            }
+        }
+    }
+
+    fn length_prefixed(messages: &[&str]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for message in messages {
+            Framing::LengthPrefixed.write(&mut bytes, message).unwrap();
+        }
+        bytes
+    }
+
+    mod newline {
+        use super::*;
+
+        #[test]
+        fn lines_are_split_and_trimmed() {
+            let frames = read_all(Framing::Newline, b"{\"type\":\"Connected\"}\r\n\n{\"type\":\"State\"}");
+            assert_eq!(frames, vec![Ok(r#"{"type":"Connected"}"#.to_string()), Ok(r#"{"type":"State"}"#.to_string())]);
+        }
+
+        #[test]
+        fn invalid_utf8_is_malformed_but_reading_continues() {
+            let frames = read_all(Framing::Newline, b"\xff\n{}\n");
+            assert!(frames[0].as_ref().unwrap_err().starts_with("malformed frame"));
+            assert_eq!(frames[1], Ok("{}".to_string()));
+        }
+
+        #[test]
+        fn messages_with_newlines_are_refused() {
+            let mut out = Vec::new();
+            assert!(matches!(Framing::Newline.write(&mut out, "{}\n{}"), Err(TransportError::Newline)));
+            Framing::Newline.write(&mut out, "{}").unwrap();
+            assert_eq!(out, b"{}\n");
+        }
+
+        #[test]
+        fn oversized_line_is_fatal() {
+            let mut reader = Cursor::new(vec![b'a'; MAX_FRAME_LEN + 1]);
+            assert!(matches!(Framing::Newline.read(&mut reader).unwrap(), Frame::Fatal(_)));
+
+            let mut reader = Cursor::new(vec![b'a'; MAX_FRAME_LEN]);
+            assert!(matches!(Framing::Newline.read(&mut reader).unwrap(), Frame::Message(_)));
+        }
+    }
+
+    mod length_prefixed {
+        use super::*;
+
+        #[test]
+        fn frames_round_trip() {
+            let frames = read_all(Framing::LengthPrefixed, &length_prefixed(&["{\n  \"type\": \"Action\"\n}", "{}"]));
+            assert_eq!(frames, vec![Ok("{\n  \"type\": \"Action\"\n}".to_string()), Ok("{}".to_string())]);
+        }
+
+        #[test]
+        fn length_is_big_endian() {
+            assert_eq!(length_prefixed(&["{}"]), b"\x00\x00\x00\x02{}");
+        }
+
+        #[test]
+        fn truncated_frame_is_fatal() {
+            let mut bytes = length_prefixed(&["{\"type\":\"Action\"}"]);
+            bytes.truncate(10);
+            let frames = read_all(Framing::LengthPrefixed, &bytes);
+            assert_eq!(frames, vec![Err("malformed frame: stream ended after 6 of 17 bytes".to_string())]);
+        }
+
+        #[test]
+        fn truncated_length_is_fatal() {
+            let frames = read_all(Framing::LengthPrefixed, b"\x00\x00");
+            assert_eq!(frames, vec![Err("malformed frame: stream ended inside a 2 byte length".to_string())]);
+        }
+
+        #[test]
+        fn oversized_length_is_fatal_without_reading_it() {
+            let mut reader = Cursor::new(b"\xff\xff\xff\xff{}".to_vec());
+            assert!(matches!(Framing::LengthPrefixed.read(&mut reader).unwrap(), Frame::Fatal(_)));
+
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).unwrap();
+            assert_eq!(rest, "{}");
+        }
+    }
+
+    mod json {
+        use super::*;
+
+        #[test]
+        fn pretty_printed_objects_are_split() {
+            let input = b"{\n  \"type\": \"Action\",\n  \"moves\": [{\"x\": 1}]\n}\n{\"type\":\"Action\"}{}";
+            let frames = read_all(Framing::Json, input);
+            assert_eq!(frames, vec![
+                Ok("{\n  \"type\": \"Action\",\n  \"moves\": [{\"x\": 1}]\n}".to_string()),
+                Ok(r#"{"type":"Action"}"#.to_string()),
+                Ok("{}".to_string()),
+            ]);
+        }
+
+        #[test]
+        fn braces_in_strings_are_ignored() {
+            let frames = read_all(Framing::Json, br#"{"say":"}{\"}"} {"say":"\\"}"#);
+            assert_eq!(frames, vec![Ok(r#"{"say":"}{\"}"}"#.to_string()), Ok(r#"{"say":"\\"}"#.to_string())]);
+        }
+
+        #[test]
+        fn garbage_between_objects_is_skipped() {
+            let frames = read_all(Framing::Json, b"{} oops\nthinking... {\"type\":\"Action\"}\ndone\n");
+            assert_eq!(frames, vec![
+                Ok("{}".to_string()),
+                Err("malformed frame: skipped 17 bytes that are not an object: `oops\nthinking...`".to_string()),
+                Ok(r#"{"type":"Action"}"#.to_string()),
+                Err("malformed frame: skipped 5 bytes that are not an object: `done`".to_string()),
+            ]);
+        }
+
+        #[test]
+        fn unfinished_object_is_fatal() {
+            let frames = read_all(Framing::Json, b"{\"type\": \"Action\"");
+            assert_eq!(frames, vec![Err("malformed frame: stream ended inside an object after 17 bytes".to_string())]);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Mutex;
+
+use crossbeam_channel::{select, Receiver, Sender};
+
+use super::{Health, Transport, TransportError};
+
+/// One end of an in-process connection, mostly useful in tests.
+pub struct MemoryTransport {
+    sender: Mutex<Option<Sender<String>>>,
+    receiver: Receiver<String>,
+    // Dropped on `close` to wake up a pending `receive`.
+    closing: Mutex<Option<Sender<()>>>,
+    closed: Receiver<()>,
+    health: Mutex<Health>,
+}
+
+impl MemoryTransport {
+    /// Two transports connected to each other.
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (a_sender, b_receiver) = crossbeam_channel::unbounded();
+        let (b_sender, a_receiver) = crossbeam_channel::unbounded();
+        (MemoryTransport::new(a_sender, a_receiver), MemoryTransport::new(b_sender, b_receiver))
+    }
+
+    fn new(sender: Sender<String>, receiver: Receiver<String>) -> Self {
+        let (closing, closed) = crossbeam_channel::bounded(0);
+        MemoryTransport{
+            sender: Mutex::new(Some(sender)),
+            receiver,
+            closing: Mutex::new(Some(closing)),
+            closed,
+            health: Mutex::new(Health::NotConnected),
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn connect(&self) -> Result<(), TransportError> {
+        let mut health = self.health.lock().unwrap();
+        match *health {
+            Health::Closed => Err(TransportError::Closed),
+            _ => {
+                *health = Health::Healthy;
+                Ok(())
+            },
+        }
+    }
+
+    fn send(&self, message: &str) -> Result<(), TransportError> {
+        match *self.health.lock().unwrap() {
+            Health::NotConnected => return Err(TransportError::NotConnected),
+            Health::Healthy => (),
+            _ => return Err(TransportError::Closed),
+        }
+
+        let sender = self.sender.lock().unwrap();
+        sender.as_ref()
+            .ok_or(TransportError::Closed)?
+            .send(message.to_string())
+            .map_err(|_| TransportError::Closed)
+    }
+
+    fn receive(&self) -> Result<Option<String>, TransportError> {
+        if self.health() == Health::NotConnected {
+            return Err(TransportError::NotConnected);
+        }
+
+        select!{
+            recv(self.receiver) -> message => match message {
+                Ok(message) => Ok(Some(message)),
+                Err(_) => {
+                    *self.health.lock().unwrap() = Health::Closed;
+                    Ok(None)
+                },
+            },
+            recv(self.closed) -> _ => Ok(None),
+        }
+    }
+
+    fn close(&self) -> Result<(), TransportError> {
+        self.sender.lock().unwrap().take();
+        self.closing.lock().unwrap().take();
+        *self.health.lock().unwrap() = Health::Closed;
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        self.health.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn connected_pair() -> (MemoryTransport, MemoryTransport) {
+        let (a, b) = MemoryTransport::pair();
+        a.connect().unwrap();
+        b.connect().unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn messages_go_both_ways() {
+        let (a, b) = connected_pair();
+        a.send("ping").unwrap();
+        assert_eq!(b.receive().unwrap().unwrap(), "ping");
+        b.send("pong").unwrap();
+        assert_eq!(a.receive().unwrap().unwrap(), "pong");
+    }
+
+    #[test]
+    fn send_before_connect_fails() {
+        let (a, _b) = MemoryTransport::pair();
+        assert_eq!(a.health(), Health::NotConnected);
+        assert!(matches!(a.send("ping"), Err(TransportError::NotConnected)));
+    }
+
+    #[test]
+    fn close_ends_both_sides() {
+        let (a, b) = connected_pair();
+        let a = Arc::new(a);
+
+        let pending = {
+            let a = a.clone();
+            thread::spawn(move || a.receive().unwrap())
+        };
+        a.close().unwrap();
+
+        assert_eq!(pending.join().unwrap(), None);
+        assert_eq!(b.receive().unwrap(), None);
+        assert_eq!(b.health(), Health::Closed);
+        assert!(matches!(a.send("ping"), Err(TransportError::Closed)));
+        assert!(matches!(a.connect(), Err(TransportError::Closed)));
+    }
+}
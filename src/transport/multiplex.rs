@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam_channel::{Receiver, Sender};
+use serde_json::Value;
+
+use crate::message as msg;
+
+use super::{Health, Transport, TransportError};
+
+/// Field that tells which registration a multiplexed message belongs to.
+pub const SLOT_FIELD: &str = "slot";
+
+/// Shares one server connection between several registrations. Every
+/// message sent through a slot gets a `slot` field, incoming messages are
+/// routed on theirs and messages without one go to every slot.
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    transport: Arc<dyn Transport>,
+    slots: Mutex<HashMap<u32, Sender<String>>>,
+    // Locked before `slots` when both are needed.
+    connection: Mutex<Connection>,
+}
+
+struct Connection {
+    started: bool,
+    // Counts connections, so the reader of a closed one leaves the slots of
+    // the next one alone.
+    generation: u64,
+}
+
+impl Multiplexer {
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Multiplexer{
+            shared: Arc::new(Shared{
+                transport,
+                slots: Mutex::new(HashMap::new()),
+                connection: Mutex::new(Connection{ started: false, generation: 0 }),
+            }),
+        }
+    }
+
+    /// Transport for one registration. The shared connection is opened when
+    /// the first slot connects and closed when the last slot closes. A
+    /// closed slot can connect again, which opens the shared connection
+    /// again if needed.
+    pub fn slot(&self, slot: u32) -> SlotTransport {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        self.shared.slots.lock().unwrap().insert(slot, sender);
+        SlotTransport{
+            slot,
+            shared: self.shared.clone(),
+            receiver: Mutex::new(receiver),
+            health: Mutex::new(Health::NotConnected),
+        }
+    }
+}
+
+impl Shared {
+    fn start(self: &Arc<Self>) -> Result<(), TransportError> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.started {
+            return Ok(());
+        }
+        self.transport.connect()?;
+        connection.started = true;
+        connection.generation += 1;
+        let generation = connection.generation;
+
+        let shared = self.clone();
+        thread::spawn(move || {
+            loop {
+                match shared.transport.receive() {
+                    Ok(Some(message)) => shared.route(message),
+                    Ok(None) => break,
+                    Err(TransportError::Message(e)) => tracing::warn!(error = %e, "dropping message"),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "receive on multiplexed transport");
+                        break;
+                    },
+                }
+            }
+            // Every slot sees the shared connection end, unless it was closed
+            // on purpose and maybe opened again already.
+            let mut connection = shared.connection.lock().unwrap();
+            if connection.generation == generation {
+                connection.started = false;
+                shared.slots.lock().unwrap().clear();
+            }
+        });
+        Ok(())
+    }
+
+    fn route(&self, message: String) {
+        let mut value: Value = match serde_json::from_str(&message) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!(error = %e, "dropping message");
+                return;
+            },
+        };
+
+        let slots = self.slots.lock().unwrap();
+        let slot = value.as_object_mut()
+            .and_then(|map| map.remove(SLOT_FIELD))
+            .and_then(|slot| slot.as_u64());
+        match slot {
+            Some(slot) => match slots.get(&(slot as u32)) {
+                Some(sender) => {
+                    let _ = sender.send(value.to_string());
+                },
+                None => tracing::warn!(slot, "dropping message for unknown slot"),
+            },
+            None => for sender in slots.values() {
+                let _ = sender.send(message.clone());
+            },
+        }
+    }
+}
+
+pub struct SlotTransport {
+    slot: u32,
+    shared: Arc<Shared>,
+    // Replaced when a closed slot connects again.
+    receiver: Mutex<Receiver<String>>,
+    health: Mutex<Health>,
+}
+
+impl Transport for SlotTransport {
+    fn connect(&self) -> Result<(), TransportError> {
+        if let Entry::Vacant(slot) = self.shared.slots.lock().unwrap().entry(self.slot) {
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            slot.insert(sender);
+            *self.receiver.lock().unwrap() = receiver;
+        }
+        self.shared.start()?;
+        *self.health.lock().unwrap() = Health::Healthy;
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<(), TransportError> {
+        match *self.health.lock().unwrap() {
+            Health::NotConnected => return Err(TransportError::NotConnected),
+            Health::Healthy => (),
+            _ => return Err(TransportError::Closed),
+        }
+
+        let mut value: Value = serde_json::from_str(message)
+            .map_err(|e| msg::MessageError::Deserialize{ source: e })?;
+        match value.as_object_mut() {
+            Some(map) => map.insert(SLOT_FIELD.to_string(), self.slot.into()),
+            None => return Err(msg::MessageError::Frame("expected a JSON object".to_string()).into()),
+        };
+        self.shared.transport.send(&value.to_string())
+    }
+
+    fn receive(&self) -> Result<Option<String>, TransportError> {
+        if *self.health.lock().unwrap() == Health::NotConnected {
+            return Err(TransportError::NotConnected);
+        }
+        // Not locked while waiting, so a reconnect can swap it.
+        let receiver = self.receiver.lock().unwrap().clone();
+        Ok(receiver.recv().ok())
+    }
+
+    /// Close this slot only, the others keep running.
+    fn close(&self) -> Result<(), TransportError> {
+        *self.health.lock().unwrap() = Health::Closed;
+        let mut connection = self.shared.connection.lock().unwrap();
+        let mut slots = self.shared.slots.lock().unwrap();
+        slots.remove(&self.slot);
+        if slots.is_empty() && connection.started {
+            connection.started = false;
+            connection.generation += 1;
+            return self.shared.transport.close();
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match self.health.lock().unwrap().clone() {
+            Health::Healthy => self.shared.transport.health(),
+            health => health,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+
+    fn multiplexer() -> (Multiplexer, MemoryTransport) {
+        let (ours, server) = MemoryTransport::pair();
+        server.connect().unwrap();
+        (Multiplexer::new(Arc::new(ours)), server)
+    }
+
+    #[test]
+    fn outgoing_messages_are_tagged_with_their_slot() {
+        let (mux, server) = multiplexer();
+        let first = mux.slot(0);
+        let second = mux.slot(1);
+        first.connect().unwrap();
+        second.connect().unwrap();
+
+        second.send(r#"{"type":"Action","x":1}"#).unwrap();
+        first.send(r#"{"type":"Action","x":2}"#).unwrap();
+
+        let received: Value = serde_json::from_str(&server.receive().unwrap().unwrap()).unwrap();
+        assert_eq!(received, serde_json::json!({"type": "Action", "x": 1, "slot": 1}));
+        let received: Value = serde_json::from_str(&server.receive().unwrap().unwrap()).unwrap();
+        assert_eq!(received, serde_json::json!({"type": "Action", "x": 2, "slot": 0}));
+    }
+
+    #[test]
+    fn incoming_messages_are_routed_on_their_slot() {
+        let (mux, server) = multiplexer();
+        let first = mux.slot(0);
+        let second = mux.slot(1);
+        first.connect().unwrap();
+        second.connect().unwrap();
+
+        server.send(r#"{"type":"Connected"}"#).unwrap();
+        server.send(r#"{"type":"RegisterSuccess","id":7,"slot":1}"#).unwrap();
+        server.send(r#"{"type":"RegisterSuccess","id":3,"slot":0}"#).unwrap();
+
+        let receive = |slot: &SlotTransport| -> Value {
+            serde_json::from_str(&slot.receive().unwrap().unwrap()).unwrap()
+        };
+        assert_eq!(receive(&first), serde_json::json!({"type": "Connected"}));
+        assert_eq!(receive(&first), serde_json::json!({"type": "RegisterSuccess", "id": 3}));
+        assert_eq!(receive(&second), serde_json::json!({"type": "Connected"}));
+        assert_eq!(receive(&second), serde_json::json!({"type": "RegisterSuccess", "id": 7}));
+    }
+
+    #[test]
+    fn closing_a_slot_leaves_the_others_running() {
+        let (mux, server) = multiplexer();
+        let first = mux.slot(0);
+        let second = mux.slot(1);
+        first.connect().unwrap();
+        second.connect().unwrap();
+
+        first.close().unwrap();
+        assert_eq!(first.receive().unwrap(), None);
+        assert!(matches!(first.send("{}"), Err(TransportError::Closed)));
+
+        server.send(r#"{"type":"State"}"#).unwrap();
+        assert_eq!(second.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+        assert_eq!(second.health(), Health::Healthy);
+
+        second.close().unwrap();
+        assert_eq!(server.receive().unwrap(), None);
+    }
+
+    #[test]
+    fn closed_slot_connects_again() {
+        let (mux, server) = multiplexer();
+        let first = mux.slot(0);
+        let second = mux.slot(1);
+        first.connect().unwrap();
+        second.connect().unwrap();
+
+        first.close().unwrap();
+        assert_eq!(first.receive().unwrap(), None);
+        first.connect().unwrap();
+
+        server.send(r#"{"type":"State","slot":0}"#).unwrap();
+        assert_eq!(first.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+        first.send(r#"{"type":"Action"}"#).unwrap();
+        let received: Value = serde_json::from_str(&server.receive().unwrap().unwrap()).unwrap();
+        assert_eq!(received, serde_json::json!({"type": "Action", "slot": 0}));
+    }
+
+    #[test]
+    fn last_slot_opens_the_shared_connection_again() {
+        use crate::transport::tcp::TcpTransport;
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mux = Multiplexer::new(Arc::new(TcpTransport::new(listener.local_addr().unwrap().to_string())));
+        let slot = mux.slot(3);
+        slot.connect().unwrap();
+        let (first, _) = listener.accept().unwrap();
+
+        slot.close().unwrap();
+        assert_eq!(BufReader::new(first).lines().count(), 0);
+
+        slot.connect().unwrap();
+        let (second, _) = listener.accept().unwrap();
+        slot.send(r#"{"type":"Action"}"#).unwrap();
+        let line = BufReader::new(second.try_clone().unwrap()).lines().next().unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap(), serde_json::json!({"type": "Action", "slot": 3}));
+
+        // The reader of the first connection must not end the new one.
+        let mut second = second;
+        std::io::Write::write_all(&mut second, b"{\"type\":\"State\",\"slot\":3}\n").unwrap();
+        assert_eq!(slot.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+    }
+
+    #[test]
+    fn closed_connection_ends_every_slot() {
+        let (mux, server) = multiplexer();
+        let first = mux.slot(0);
+        let second = mux.slot(1);
+        first.connect().unwrap();
+        second.connect().unwrap();
+
+        server.close().unwrap();
+        assert_eq!(first.receive().unwrap(), None);
+        assert_eq!(second.receive().unwrap(), None);
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use super::{Framed, Framing, Health, Transport, TransportError};
+
+type Streams = (Box<dyn BufRead + Send>, Box<dyn Write + Send>);
+
+/// Messages over standard input and output, for running as a bot that is
+/// started by another process. Newline delimited unless set otherwise.
+pub struct StdioTransport {
+    // Taken on `connect`.
+    streams: Mutex<Option<Streams>>,
+    framed: Framed,
+}
+
+impl StdioTransport {
+    pub fn new() -> Self {
+        StdioTransport::with_streams(BufReader::new(io::stdin()), io::stdout())
+    }
+
+    /// Use other streams in place of standard input and output.
+    pub fn with_streams<R, W>(reader: R, writer: W) -> Self
+            where R: BufRead + Send + 'static, W: Write + Send + 'static {
+        StdioTransport{
+            streams: Mutex::new(Some((Box::new(reader), Box::new(writer)))),
+            framed: Framed::new(Framing::Newline),
+        }
+    }
+
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framed = Framed::new(framing);
+        self
+    }
+}
+
+impl Default for StdioTransport {
+    fn default() -> Self {
+        StdioTransport::new()
+    }
+}
+
+impl Transport for StdioTransport {
+    fn connect(&self) -> Result<(), TransportError> {
+        let (reader, writer) = self.streams.lock().unwrap().take()
+            .ok_or(TransportError::Closed)?;
+        self.framed.open(reader, writer);
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<(), TransportError> {
+        self.framed.send(message)
+    }
+
+    fn receive(&self) -> Result<Option<String>, TransportError> {
+        self.framed.receive()
+    }
+
+    /// Stops writing, standard input can't be interrupted so a pending
+    /// `receive` returns when it reaches its end.
+    fn close(&self) -> Result<(), TransportError> {
+        self.framed.close();
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        self.framed.health()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    #[derive(Clone,Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_lines() {
+        let output = SharedBuffer::default();
+        let transport = StdioTransport::with_streams(Cursor::new(b"{\"type\":\"State\"}\n".to_vec()), output.clone());
+        transport.connect().unwrap();
+
+        assert_eq!(transport.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+        assert_eq!(transport.receive().unwrap(), None);
+
+        transport.send(r#"{"type":"Action"}"#).unwrap();
+        assert_eq!(*output.0.lock().unwrap(), b"{\"type\":\"Action\"}\n");
+    }
+
+    #[test]
+    fn pretty_printed_json_with_json_framing() {
+        let input = b"{\n  \"type\": \"Action\"\n}\n".to_vec();
+        let transport = StdioTransport::with_streams(Cursor::new(input), io::sink()).with_framing(Framing::Json);
+        transport.connect().unwrap();
+
+        assert_eq!(transport.receive().unwrap().unwrap(), "{\n  \"type\": \"Action\"\n}");
+    }
+
+    #[test]
+    fn connect_only_once() {
+        let transport = StdioTransport::with_streams(Cursor::new(Vec::new()), io::sink());
+        assert!(matches!(transport.receive(), Err(TransportError::NotConnected)));
+        transport.connect().unwrap();
+        assert!(matches!(transport.connect(), Err(TransportError::Closed)));
+    }
+
+    #[test]
+    fn close_stops_writing() {
+        let transport = StdioTransport::with_streams(Cursor::new(Vec::new()), io::sink());
+        transport.connect().unwrap();
+        transport.close().unwrap();
+        assert_eq!(transport.health(), Health::Closed);
+        assert!(matches!(transport.send("{}"), Err(TransportError::Closed)));
+    }
+}
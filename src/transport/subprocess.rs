@@ -0,0 +1,148 @@
+use std::io::{self, BufReader, BufWriter};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use super::{Framed, Framing, Health, Transport, TransportError};
+
+/// Runs a bot as a child process and exchanges messages over its standard
+/// input and output, newline delimited unless set otherwise. Its standard
+/// error is inherited.
+pub struct SubprocessTransport {
+    command: Vec<String>,
+    child: Mutex<Option<Child>>,
+    framed: Framed,
+}
+
+impl SubprocessTransport {
+    /// `command` is the program followed by its arguments.
+    pub fn new(command: Vec<String>) -> Self {
+        SubprocessTransport{
+            command,
+            child: Mutex::new(None),
+            framed: Framed::new(Framing::Newline),
+        }
+    }
+
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framed = Framed::new(framing);
+        self
+    }
+}
+
+impl Transport for SubprocessTransport {
+    fn connect(&self) -> Result<(), TransportError> {
+        let target = self.command.join(" ");
+        let connect_error = |e| TransportError::Connect{ target: target.clone(), source: e };
+        let (program, args) = self.command.split_first()
+            .ok_or_else(|| connect_error(io::Error::new(io::ErrorKind::InvalidInput, "empty command")))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(connect_error)?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        self.framed.open(Box::new(BufReader::new(stdout)), Box::new(BufWriter::new(stdin)));
+        *self.child.lock().unwrap() = Some(child);
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<(), TransportError> {
+        self.framed.send(message)
+    }
+
+    fn receive(&self) -> Result<Option<String>, TransportError> {
+        self.framed.receive()
+    }
+
+    /// Close the bot's input and stop the process.
+    fn close(&self) -> Result<(), TransportError> {
+        self.framed.close();
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            if child.try_wait()?.is_none() {
+                child.kill()?;
+            }
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            match child.try_wait() {
+                Ok(Some(status)) => return Health::Failed(format!("bot exited with {}", status)),
+                Ok(None) => (),
+                Err(e) => return Health::Failed(e.to_string()),
+            }
+        }
+        self.framed.health()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn command(command: &[&str]) -> Vec<String> {
+        command.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn exchanges_lines_with_the_process() {
+        let transport = SubprocessTransport::new(command(&["cat"]));
+        transport.connect().unwrap();
+        assert_eq!(transport.health(), Health::Healthy);
+
+        transport.send(r#"{"type":"State"}"#).unwrap();
+        assert_eq!(transport.receive().unwrap().unwrap(), r#"{"type":"State"}"#);
+
+        transport.close().unwrap();
+        assert_eq!(transport.health(), Health::Closed);
+    }
+
+    #[test]
+    fn length_prefixed_frames_with_the_process() {
+        let transport = SubprocessTransport::new(command(&["cat"])).with_framing(Framing::LengthPrefixed);
+        transport.connect().unwrap();
+
+        transport.send("{\n  \"type\": \"Action\"\n}").unwrap();
+        assert_eq!(transport.receive().unwrap().unwrap(), "{\n  \"type\": \"Action\"\n}");
+        transport.close().unwrap();
+    }
+
+    #[test]
+    fn malformed_frame_does_not_hang() {
+        let transport = SubprocessTransport::new(command(&["printf", "\\000\\000\\000\\011{}"]))
+            .with_framing(Framing::LengthPrefixed);
+        transport.connect().unwrap();
+
+        assert!(matches!(transport.receive(), Err(TransportError::Message(_))));
+        assert!(matches!(transport.receive(), Err(TransportError::Closed)));
+    }
+
+    #[test]
+    fn exited_process_is_unhealthy() {
+        let transport = SubprocessTransport::new(command(&["sh", "-c", "exit 3"]));
+        transport.connect().unwrap();
+        assert_eq!(transport.receive().unwrap(), None);
+
+        thread::sleep(Duration::from_millis(50));
+        match transport.health() {
+            Health::Failed(reason) => assert!(reason.contains('3'), "{}", reason),
+            health => panic!("Expected a failed health but got {:?}", health),
+        }
+    }
+
+    #[test]
+    fn unknown_program_fails_to_connect() {
+        let transport = SubprocessTransport::new(command(&["wartemis-no-such-bot"]));
+        assert!(matches!(transport.connect(), Err(TransportError::Connect{ .. })));
+
+        let transport = SubprocessTransport::new(Vec::new());
+        assert!(matches!(transport.connect(), Err(TransportError::Connect{ .. })));
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::{self, BufReader, BufWriter};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use super::{Framed, Framing, Health, Transport, TransportError};
+
+/// Newline delimited JSON over a plain TCP socket, as an alternative to a
+/// WebSocket connection with the server. Optionally wrapped in TLS.
+pub struct TcpTransport {
+    addr: String,
+    connect_timeout: Option<Duration>,
+    stream: Mutex<Option<TcpStream>>,
+    framed: Framed,
+    #[cfg(feature = "tls")]
+    tls: Option<(String, Arc<rustls::ClientConfig>)>,
+}
+
+impl TcpTransport {
+    pub fn new<S: Into<String>>(addr: S) -> Self {
+        TcpTransport{
+            addr: addr.into(),
+            connect_timeout: None,
+            stream: Mutex::new(None),
+            framed: Framed::new(Framing::Newline),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Give up on connecting after `timeout`, instead of waiting for the
+    /// operating system to.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    fn open(&self) -> io::Result<TcpStream> {
+        let timeout = match self.connect_timeout {
+            Some(timeout) => timeout,
+            None => return TcpStream::connect(&self.addr),
+        };
+        let mut error = io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing");
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Speak TLS, verifying the server certificate against `server_name`.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, server_name: &str, config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some((server_name.to_string(), config));
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    fn connect_tls(&self, server_name: &str, config: &Arc<rustls::ClientConfig>) -> Result<(), TransportError> {
+        let sock = self.open().map_err(|e| TransportError::Connect{ target: self.addr.clone(), source: e })?;
+        let stream = crate::tls::handshake(sock, &self.addr, server_name, config.clone())?;
+        let sock = stream.get_ref().try_clone()
+            .map_err(|e| TransportError::Connect{ target: self.addr.clone(), source: e })?;
+        let (reader, writer) = crate::tls::split(stream)
+            .map_err(|e| TransportError::Connect{ target: self.addr.clone(), source: e })?;
+        self.framed.open(Box::new(BufReader::new(reader)), Box::new(BufWriter::new(writer)));
+        *self.stream.lock().unwrap() = Some(sock);
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn connect(&self) -> Result<(), TransportError> {
+        #[cfg(feature = "tls")]
+        if let Some((server_name, config)) = &self.tls {
+            return self.connect_tls(server_name, config);
+        }
+        let connect_error = |e| TransportError::Connect{ target: self.addr.clone(), source: e };
+
+        let stream = self.open().map_err(connect_error)?;
+        let reader = stream.try_clone().map_err(connect_error)?;
+        let writer = stream.try_clone().map_err(connect_error)?;
+        self.framed.open(Box::new(BufReader::new(reader)), Box::new(BufWriter::new(writer)));
+        *self.stream.lock().unwrap() = Some(stream);
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<(), TransportError> {
+        self.framed.send(message)
+    }
+
+    fn receive(&self) -> Result<Option<String>, TransportError> {
+        self.framed.receive()
+    }
+
+    fn close(&self) -> Result<(), TransportError> {
+        self.framed.close();
+        match self.stream.lock().unwrap().take() {
+            Some(stream) => Ok(stream.shutdown(Shutdown::Both)?),
+            None => Ok(()),
+        }
+    }
+
+    fn health(&self) -> Health {
+        self.framed.health()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel;
+    use crate::client::Client;
+    use crate::handler;
+    use crate::transport::memory::MemoryTransport;
+    use std::io::{BufRead, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Connect a transport to a local stand-in server.
+    fn connect() -> (TcpTransport, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = TcpTransport::new(listener.local_addr().unwrap().to_string());
+        transport.connect().unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (transport, server)
+    }
+
+    #[test]
+    fn connect_to_closed_port_fails() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let transport = TcpTransport::new(addr.to_string());
+        assert!(matches!(transport.connect(), Err(TransportError::Connect{ .. })));
+        assert_eq!(transport.health(), Health::NotConnected);
+    }
+
+    #[test]
+    fn connect_timeout_resolves_the_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+        let transport = TcpTransport::new(addr).connect_timeout(Duration::from_secs(5));
+        transport.connect().unwrap();
+        assert_eq!(transport.health(), Health::Healthy);
+    }
+
+    #[test]
+    fn messages_are_written_as_lines() {
+        let (transport, server) = connect();
+        transport.send(r#"{"type":"Action","x":1}"#).unwrap();
+        transport.send(r#"{"type":"Action","x":2}"#).unwrap();
+
+        let mut lines = BufReader::new(server).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), r#"{"type":"Action","x":1}"#);
+        assert_eq!(lines.next().unwrap().unwrap(), r#"{"type":"Action","x":2}"#);
+    }
+
+    #[test]
+    fn lines_split_over_reads_are_joined() {
+        let (transport, mut server) = connect();
+        for part in &[r#"{"type":"#, r#""State","#, "\"turn\":1}\n{\"type\":\"State\",\"turn\":2}\r\n\n"] {
+            server.write_all(part.as_bytes()).unwrap();
+            server.flush().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(transport.receive().unwrap().unwrap(), r#"{"type":"State","turn":1}"#);
+        assert_eq!(transport.receive().unwrap().unwrap(), r#"{"type":"State","turn":2}"#);
+    }
+
+    #[test]
+    fn very_large_states_are_received_whole() {
+        let (transport, mut server) = connect();
+        let state = format!(r#"{{"type":"State","map":"{}"}}"#, "x".repeat(8 * 1024 * 1024));
+
+        let sent = state.clone();
+        thread::spawn(move || {
+            server.write_all(sent.as_bytes()).unwrap();
+            server.write_all(b"\n").unwrap();
+        });
+
+        assert_eq!(transport.receive().unwrap().unwrap(), state);
+    }
+
+    #[test]
+    fn closed_connection_ends_receive() {
+        let (transport, server) = connect();
+        drop(server);
+        assert_eq!(transport.receive().unwrap(), None);
+        assert_eq!(transport.health(), Health::Closed);
+    }
+
+    #[test]
+    fn close_shuts_down_the_connection() {
+        let (transport, mut server) = connect();
+        transport.close().unwrap();
+        assert_eq!(server.read(&mut [0; 16]).unwrap(), 0);
+        assert!(matches!(transport.send("{}"), Err(TransportError::Closed)));
+    }
+
+    #[test]
+    fn client_registers_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_transport = Arc::new(TcpTransport::new(listener.local_addr().unwrap().to_string()));
+        let (bot_transport, _bot) = MemoryTransport::pair();
+        let config = handler::ClientConfig::builder().game("test_game").name("test_bot").build().unwrap();
+
+        thread::spawn(move || {
+            let handler = Box::new(handler::MessageHandler::new(config));
+            let client: Client = Client::with_transports(handler, server_transport, Arc::new(bot_transport),
+                &channel::ChannelConfig::default()).unwrap();
+            let _ = client.start();
+        });
+
+        let (server, _) = listener.accept().unwrap();
+        let mut writer = server.try_clone().unwrap();
+        writer.write_all(b"{\"type\":\"Connected\"}\n").unwrap();
+        let register = BufReader::new(server).lines().next().unwrap().unwrap();
+        assert_eq!(register, r#"{"type":"Register","clientType":"bot","game":"test_game","name":"test_bot"}"#);
+    }
+}
@@ -0,0 +1,115 @@
+use serde_json::{json, Value};
+
+use crate::message as msg;
+
+/// Checks the content of an `Action` before it is sent to the server.
+pub trait ActionValidator: Send + Sync {
+    /// Returns a description of what is wrong with an invalid action.
+    fn validate(&self, action: &Value) -> Result<(), String>;
+}
+
+impl<F> ActionValidator for F
+    where F: Fn(&Value) -> Result<(), String> + Send + Sync {
+    fn validate(&self, action: &Value) -> Result<(), String> {
+        self(action)
+    }
+}
+
+/// Local `Error` message sent back to the bot for an action that was rejected
+/// before it reached the server.
+pub fn invalid_action_error(reason: &str) -> msg::MessageContent {
+    msg::MessageContent{
+        content: json!({"message": format!("invalid action: {}", reason)}),
+    }
+}
+
+/// Validates actions against a game specific JSON Schema.
+#[cfg(feature = "schema")]
+pub struct JsonSchemaValidator {
+    validator: jsonschema::Validator,
+}
+
+#[cfg(feature = "schema")]
+impl JsonSchemaValidator {
+    pub fn new(schema: &Value) -> Result<Self, String> {
+        jsonschema::validator_for(schema)
+            .map(|validator| JsonSchemaValidator{ validator })
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "schema")]
+impl ActionValidator for JsonSchemaValidator {
+    fn validate(&self, action: &Value) -> Result<(), String> {
+        let errors: Vec<String> = self.validator.iter_errors(action)
+            .map(|e| format!("`{}`: {}", e.instance_path, e))
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only_moves(action: &Value) -> Result<(), String> {
+        match action["move"].as_i64() {
+            Some(_) => Ok(()),
+            None => Err("`move` should be a number".to_string()),
+        }
+    }
+
+    #[test]
+    fn closure_can_be_used_as_validator() {
+        let validator: Box<dyn ActionValidator> = Box::new(only_moves);
+
+        assert!(validator.validate(&json!({"move": 1})).is_ok());
+        assert_eq!(
+            validator.validate(&json!({"move": "up"})),
+            Err("`move` should be a number".to_string()));
+    }
+
+    #[test]
+    fn invalid_action_error_contains_reason() {
+        let error = invalid_action_error("too far");
+        assert_eq!(error.content["message"], "invalid action: too far");
+    }
+
+    #[cfg(feature = "schema")]
+    mod schema {
+        use super::*;
+
+        fn move_schema() -> Value {
+            json!({
+                "type": "object",
+                "required": ["move"],
+                "properties": {
+                    "move": {"type": "integer", "minimum": 0}
+                }
+            })
+        }
+
+        #[test]
+        fn valid_action_passes_schema() {
+            let validator = JsonSchemaValidator::new(&move_schema()).unwrap();
+            assert!(validator.validate(&json!({"move": 3})).is_ok());
+        }
+
+        #[test]
+        fn invalid_action_reports_path() {
+            let validator = JsonSchemaValidator::new(&move_schema()).unwrap();
+            let reason = validator.validate(&json!({"move": -1})).err().unwrap();
+            assert!(reason.starts_with("`/move`"), "unexpected reason: {}", reason);
+        }
+
+        #[test]
+        fn invalid_schema_is_rejected() {
+            assert!(JsonSchemaValidator::new(&json!({"type": 12})).is_err());
+        }
+    }
+}